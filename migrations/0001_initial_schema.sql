@@ -1,6 +1,7 @@
-\c searxiv;
+-- Schema created by db/init.sql before archivist managed migrations itself.
+-- Everything is conditional so that such databases can adopt migrations.
 
-CREATE TABLE papers (
+CREATE TABLE IF NOT EXISTS papers (
   id SERIAL PRIMARY KEY,
   arxiv_id VARCHAR NOT NULL UNIQUE,
   title VARCHAR NOT NULL,
@@ -9,33 +10,37 @@ CREATE TABLE papers (
   body TEXT NOT NULL
 );
 
-CREATE TABLE authors (
+CREATE TABLE IF NOT EXISTS authors (
   id SERIAL PRIMARY KEY,
   name VARCHAR NOT NULL UNIQUE
 );
 
-CREATE TABLE paper_author (
+CREATE TABLE IF NOT EXISTS paper_author (
   paper_id INTEGER REFERENCES papers (id),
   author_id INTEGER REFERENCES authors (id),
   PRIMARY KEY (paper_id, author_id)
 );
 
 
-CREATE TABLE subjects (
+CREATE TABLE IF NOT EXISTS subjects (
   id SERIAL PRIMARY KEY,
   name VARCHAR NOT NULL UNIQUE
 );
 
-CREATE TABLE paper_subject (
+CREATE TABLE IF NOT EXISTS paper_subject (
   paper_id INTEGER REFERENCES papers (id),
   subject_id INTEGER REFERENCES subjects (id),
   PRIMARY KEY (paper_id, subject_id)
 );
 
 
-CREATE TYPE status AS ENUM ('idle', 'processing', 'done');
+DO $$ BEGIN
+  CREATE TYPE status AS ENUM ('idle', 'processing', 'done');
+EXCEPTION
+  WHEN duplicate_object THEN NULL;
+END $$;
 
-CREATE TABLE tasks (
+CREATE TABLE IF NOT EXISTS tasks (
   submission_date DATE PRIMARY KEY,
   status status NOT NULL,
   processing_start timestamp,
@@ -0,0 +1,47 @@
+-- Full-text search over papers. Body is truncated because tsvector can not be
+-- larger than 1MB
+ALTER TABLE papers ADD COLUMN search tsvector GENERATED ALWAYS AS (
+  setweight(to_tsvector('english', title), 'A') ||
+  setweight(to_tsvector('english', description), 'B') ||
+  setweight(to_tsvector('english', left(body, 262144)), 'C')
+) STORED;
+
+CREATE INDEX papers_search_idx ON papers USING GIN (search);
+CREATE INDEX papers_submission_date_idx ON papers (submission_date);
+
+-- Paper revisions, current one is always stored in papers
+ALTER TABLE papers ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE paper_versions (
+  paper_id INTEGER REFERENCES papers (id),
+  version INTEGER NOT NULL,
+  title VARCHAR NOT NULL,
+  description TEXT NOT NULL,
+  body TEXT NOT NULL,
+  superseded_at timestamp NOT NULL,
+  PRIMARY KEY (paper_id, version)
+);
+
+-- Browsing by authors and subjects
+CREATE INDEX authors_name_prefix_idx ON authors (lower(name) text_pattern_ops);
+CREATE INDEX paper_author_author_idx ON paper_author (author_id);
+CREATE INDEX paper_subject_subject_idx ON paper_subject (subject_id);
+
+-- Leases, worker attribution and retries of tasks
+ALTER TYPE status ADD VALUE 'failed';
+
+ALTER TABLE tasks
+  ADD COLUMN lease_id UUID,
+  ADD COLUMN lease_expires_at timestamp,
+  ADD COLUMN worker VARCHAR,
+  ADD COLUMN submitted_by VARCHAR,
+  ADD COLUMN attempts INTEGER NOT NULL DEFAULT 0,
+  ADD COLUMN last_error TEXT,
+  ADD COLUMN retry_after timestamp;
+
+CREATE TABLE workers (
+  name VARCHAR PRIMARY KEY,
+  last_seen timestamp NOT NULL,
+  completed INTEGER NOT NULL DEFAULT 0,
+  failed INTEGER NOT NULL DEFAULT 0
+);
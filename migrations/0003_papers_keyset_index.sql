@@ -0,0 +1,3 @@
+-- Papers are listed in (submission_date, id) order with keyset pagination
+CREATE INDEX papers_submission_date_id_idx ON papers (submission_date, id);
+DROP INDEX papers_submission_date_idx;
@@ -0,0 +1,10 @@
+-- API keys, only SHA-256 of the key is stored
+CREATE TYPE scope AS ENUM ('read', 'scraper', 'admin');
+
+CREATE TABLE api_keys (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  key_hash VARCHAR NOT NULL UNIQUE,
+  scope scope NOT NULL,
+  created_at timestamp NOT NULL DEFAULT now()
+);
@@ -0,0 +1,3 @@
+-- Tasks with higher priority are given to scrapers first
+ALTER TABLE tasks ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX tasks_dispatch_idx ON tasks (status, priority DESC, submission_date);
@@ -0,0 +1,2 @@
+-- Tasks cancelled by admin are kept but never given to scrapers
+ALTER TYPE status ADD VALUE 'cancelled';
@@ -0,0 +1,5 @@
+-- Tasks can cover a single arXiv category of a day, empty category means the
+-- whole day
+ALTER TABLE tasks ADD COLUMN category VARCHAR NOT NULL DEFAULT '';
+ALTER TABLE tasks DROP CONSTRAINT tasks_pkey;
+ALTER TABLE tasks ADD PRIMARY KEY (submission_date, category);
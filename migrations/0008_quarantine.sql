@@ -0,0 +1,15 @@
+-- Papers of submissions rejected by validation, kept for review
+CREATE TABLE quarantine (
+  id SERIAL PRIMARY KEY,
+  submission_date DATE NOT NULL,
+  category VARCHAR NOT NULL DEFAULT '',
+  worker VARCHAR,
+  line INTEGER NOT NULL,
+  arxiv_id VARCHAR,
+  -- Missing if the line could not be parsed
+  paper JSONB,
+  errors TEXT[] NOT NULL,
+  created_at TIMESTAMP NOT NULL DEFAULT now()
+);
+
+CREATE INDEX quarantine_submission_date_idx ON quarantine (submission_date);
@@ -0,0 +1,4 @@
+-- Accepted submission of a task, so that retried submissions are not ingested
+-- again. Key is either given by the scraper or a hash of the submitted file
+ALTER TABLE tasks ADD COLUMN submission_key VARCHAR;
+ALTER TABLE tasks ADD COLUMN submission_report TEXT;
@@ -0,0 +1,129 @@
+use crate::{config::Config, db, models::Scope};
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header,
+    web::Data,
+};
+use futures::future::LocalBoxFuture;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::{
+    future::{ready, Ready},
+    rc::Rc,
+};
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// Generate new random API key, it is shown to the user only once.
+pub fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Keys are random, so plain SHA-256 is enough to not keep them in database.
+pub fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+/// Take API key from `Authorization: Bearer` or `X-Api-Key` header.
+fn request_key(req: &ServiceRequest) -> Option<&str> {
+    let headers = req.headers();
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| {
+            headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+}
+
+async fn authorize(req: &ServiceRequest, required: Scope) -> actix_web::Result<()> {
+    if let Some(config) = req.app_data::<Data<Config>>() {
+        if !config.auth_enabled {
+            return Ok(());
+        }
+    }
+
+    let Some(key) = request_key(req) else {
+        return Err(actix_web::error::ErrorUnauthorized("Missing API key"));
+    };
+
+    // NOTE: database is always registered in main
+    let db = req.app_data::<Data<db::DBConnection>>().unwrap();
+    match db.get_api_key_scope(&hash_key(key)).await? {
+        None => Err(actix_web::error::ErrorUnauthorized("Invalid API key")),
+        Some(scope) if scope < required => Err(actix_web::error::ErrorForbidden(format!(
+            "API key does not have {required:?} scope"
+        ))),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Middleware which lets through only requests with API key of at least
+/// given scope.
+pub struct RequireScope(pub Scope);
+
+impl RequireScope {
+    pub fn read() -> Self {
+        RequireScope(Scope::Read)
+    }
+
+    pub fn scraper() -> Self {
+        RequireScope(Scope::Scraper)
+    }
+
+    pub fn admin() -> Self {
+        RequireScope(Scope::Admin)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequireScope
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Transform = RequireScopeMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireScopeMiddleware {
+            service: Rc::new(service),
+            scope: self.0,
+        }))
+    }
+}
+
+pub struct RequireScopeMiddleware<S> {
+    service: Rc<S>,
+    scope: Scope,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireScopeMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let scope = self.scope;
+
+        Box::pin(async move {
+            authorize(&req, scope).await?;
+            service.call(req).await
+        })
+    }
+}
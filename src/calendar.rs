@@ -0,0 +1,87 @@
+use chrono::{Datelike, NaiveDate, Weekday};
+use std::collections::HashSet;
+
+/// Days on which arXiv announces new papers: every weekday except the
+/// configured holidays.
+#[derive(Clone, Debug, Default)]
+pub struct AnnouncementCalendar {
+    holidays: HashSet<NaiveDate>,
+}
+
+impl AnnouncementCalendar {
+    pub fn new(holidays: impl IntoIterator<Item = NaiveDate>) -> Self {
+        AnnouncementCalendar {
+            holidays: holidays.into_iter().collect(),
+        }
+    }
+
+    pub fn is_announcement_day(&self, date: NaiveDate) -> bool {
+        !matches!(date.weekday(), Weekday::Sat | Weekday::Sun) && !self.holidays.contains(&date)
+    }
+
+    /// Latest `count` announcement days before `today`, newest first.
+    pub fn days_before(&self, today: NaiveDate, count: usize) -> Vec<NaiveDate> {
+        today
+            .iter_days()
+            .rev()
+            .skip(1)
+            .filter(|&date| self.is_announcement_day(date))
+            .take(count)
+            .collect()
+    }
+}
+
+impl From<&crate::config::Config> for AnnouncementCalendar {
+    fn from(config: &crate::config::Config) -> Self {
+        AnnouncementCalendar::new(config.announcement_holidays.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, month, day).unwrap()
+    }
+
+    #[test]
+    fn weekends_and_holidays_are_skipped() {
+        let calendar = AnnouncementCalendar::new([date(12, 25)]);
+
+        assert!(calendar.is_announcement_day(date(12, 20)));
+        assert!(!calendar.is_announcement_day(date(12, 21)));
+        assert!(!calendar.is_announcement_day(date(12, 22)));
+        assert!(!calendar.is_announcement_day(date(12, 25)));
+    }
+
+    #[test]
+    fn days_before_skip_weekend() {
+        let calendar = AnnouncementCalendar::default();
+
+        // Monday
+        assert_eq!(
+            calendar.days_before(date(12, 16), 2),
+            [date(12, 13), date(12, 12)]
+        );
+    }
+
+    #[test]
+    fn days_before_skip_holidays() {
+        let calendar = AnnouncementCalendar::new([date(12, 25), date(12, 26)]);
+
+        assert_eq!(
+            calendar.days_before(date(12, 27), 3),
+            [date(12, 24), date(12, 23), date(12, 20)]
+        );
+    }
+
+    #[test]
+    fn today_is_not_announced_yet() {
+        let calendar = AnnouncementCalendar::default();
+
+        // Wednesday
+        assert_eq!(calendar.days_before(date(12, 18), 1), [date(12, 17)]);
+        assert!(calendar.days_before(date(12, 18), 0).is_empty());
+    }
+}
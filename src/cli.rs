@@ -0,0 +1,42 @@
+use crate::models::Scope;
+use std::path::PathBuf;
+
+#[derive(clap::Parser, Debug)]
+#[command(about, version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(clap::Subcommand, Debug, Default)]
+pub enum Command {
+    /// Start web server (default)
+    #[default]
+    Serve,
+    /// Import NDJSON of NewPaperFull, e.g. made by /archive/export, and mark
+    /// days it covers as done
+    Import {
+        /// File to import, `-` to read from stdin
+        file: PathBuf,
+    },
+    /// Manage API keys
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommand,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum KeysCommand {
+    /// Create new API key and print it
+    Create {
+        /// Unique name of the key, e.g. who is going to use it
+        name: String,
+        #[arg(long, value_enum)]
+        scope: Scope,
+    },
+    /// List existing API keys
+    List,
+    /// Delete API key
+    Revoke { name: String },
+}
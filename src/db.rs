@@ -1,34 +1,215 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use futures::Stream;
 use sqlx::postgres::PgPoolOptions;
 
-use crate::models::{self, NewAuthor, NewPaper, NewPaperFull, NewSubject};
+use crate::config::DispatchOrder;
+use crate::models::{self, NewAuthor, NewPaperFull, NewSubject};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("database error: {0}")]
     Sqlx(#[from] sqlx::Error),
+    #[error("migration error: {0}")]
+    Migrate(#[from] sqlx::migrate::MigrateError),
+    #[error("json error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("database schema version {database} is newer than the latest known {binary}")]
+    DatabaseAhead { database: i64, binary: i64 },
+    #[error("lease for task {0} is expired or held by someone else")]
+    LeaseMismatch(chrono::NaiveDate),
+    #[error("task {0} is already submitted with different content, it can be submitted again with force")]
+    AlreadySubmitted(chrono::NaiveDate),
+    #[error("task {submission_date} is {status:?}, it can not be {action}")]
+    InvalidTransition {
+        submission_date: chrono::NaiveDate,
+        status: models::Status,
+        action: &'static str,
+    },
+}
+impl actix_web::error::ResponseError for Error {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Error::Sqlx(_) | Error::Migrate(_) | Error::Json(_) | Error::DatabaseAhead { .. } => {
+                actix_web::http::StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Error::LeaseMismatch(_)
+            | Error::AlreadySubmitted(_)
+            | Error::InvalidTransition { .. } => actix_web::http::StatusCode::CONFLICT,
+        }
+    }
 }
-impl actix_web::error::ResponseError for Error {}
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+static MIGRATOR: sqlx::migrate::Migrator = sqlx::migrate!();
+
+/// Channel notified whenever tasks may have become available.
+const TASKS_CHANNEL: &str = "tasks_available";
+
+/// Backoff stops growing at a week, otherwise it overflows after enough
+/// attempts.
+const MAX_RETRY_BACKOFF_SECONDS: f64 = 7. * 24. * 60. * 60.;
+
+/// How tasks which were failed or abandoned by scrapers are retried.
+pub struct RetryPolicy {
+    pub max_attempts: i32,
+    /// Delay before the first retry, doubled on every next attempt
+    pub backoff_seconds: u64,
+}
+
+impl From<&crate::config::Config> for RetryPolicy {
+    fn from(config: &crate::config::Config) -> Self {
+        RetryPolicy {
+            max_attempts: config.task_max_attempts,
+            backoff_seconds: config.task_retry_backoff_seconds,
+        }
+    }
+}
+
+/// Task held by a scraper, see [`DBConnection::get_leased_task`].
+pub struct LeasedTask {
+    pub category: Option<String>,
+    pub status: models::Status,
+    /// Idempotency key or content hash of the accepted submission
+    pub submission_key: Option<String>,
+    /// Report of the accepted submission as JSON
+    pub submission_report: Option<String>,
+}
+
+/// Inclusive range of submission dates, open on missing ends.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DateRange {
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PaperFilter {
+    pub range: DateRange,
+    pub subject: Option<String>,
+    pub author_id: Option<models::Id>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ExportRow {
+    arxiv_id: String,
+    title: String,
+    description: String,
+    submission_date: chrono::NaiveDate,
+    body: String,
+    version: i32,
+    authors: Vec<String>,
+    subjects: Vec<String>,
+}
+
+impl From<ExportRow> for NewPaperFull {
+    fn from(row: ExportRow) -> Self {
+        NewPaperFull {
+            arxiv_id: row.arxiv_id,
+            title: row.title,
+            description: row.description,
+            submission_date: row.submission_date,
+            body: row.body,
+            version: row.version,
+            authors: row
+                .authors
+                .into_iter()
+                .map(|name| NewAuthor { name })
+                .collect(),
+            subjects: row
+                .subjects
+                .into_iter()
+                .map(|name| NewSubject { name })
+                .collect(),
+        }
+    }
+}
+
+/// Requests waiting for tasks to appear, woken up by notifications from
+/// database.
+#[derive(Default)]
+pub struct TaskWaiters(tokio::sync::Notify);
+
+impl TaskWaiters {
+    pub fn notified(&self) -> tokio::sync::futures::Notified<'_> {
+        self.0.notified()
+    }
+}
+
+/// Utilisation of database connection pool.
+pub struct PoolStats {
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
 #[derive(Clone)]
 pub struct DBConnection {
     pool: sqlx::Pool<sqlx::Postgres>,
 }
 
 impl DBConnection {
+    /// Connect to database and bring its schema up to date.
     pub async fn new(db_url: &str) -> Result<DBConnection> {
-        Ok(DBConnection {
+        let db = DBConnection {
             pool: PgPoolOptions::new().connect(db_url).await?,
-        })
+        };
+        db.migrate().await?;
+
+        Ok(db)
+    }
+
+    /// Wrap a pool with already migrated schema.
+    #[cfg(test)]
+    pub fn from_pool(pool: sqlx::PgPool) -> DBConnection {
+        DBConnection { pool }
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        let binary = MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0);
+        let database = self.schema_version().await?;
+        if database > binary {
+            return Err(Error::DatabaseAhead { database, binary });
+        }
+
+        if database < binary {
+            log::info!("DB: migrating schema from version {database} to {binary}");
+        }
+        MIGRATOR.run(&self.pool).await?;
+
+        Ok(())
+    }
+
+    /// Latest applied migration, 0 if database was never migrated.
+    async fn schema_version(&self) -> Result<i64> {
+        let migrated = sqlx::query_scalar!("SELECT to_regclass('_sqlx_migrations') IS NOT NULL")
+            .fetch_one(&self.pool)
+            .await?
+            .unwrap(); // NOTE: it always returns a bool
+        if !migrated {
+            return Ok(0);
+        }
+
+        // NOTE: not checked at compile time, table is created by the migrator itself
+        let version: Option<i64> =
+            sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(version.unwrap_or(0))
     }
 
     #[allow(unused)]
     pub async fn get_all_papers(&self) -> Result<Vec<models::Paper>> {
-        sqlx::query_as!(models::Paper, "SELECT * FROM papers")
-            .fetch_all(&self.pool)
-            .await
-            .map_err(|e| e.into())
+        sqlx::query_as!(
+            models::Paper,
+            "SELECT id, arxiv_id, title, description, submission_date, body, version
+             FROM papers"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
     }
 
     pub async fn count_papers(&self) -> Result<i64> {
@@ -40,23 +221,51 @@ impl DBConnection {
         Ok(count)
     }
 
-    #[allow(unused)]
-    pub async fn get_paper(&self, desired_id: i32) -> Result<models::Paper> {
+    pub async fn get_paper(&self, desired_id: i32) -> Result<Option<models::Paper>> {
         sqlx::query_as!(
             models::Paper,
-            "SELECT * FROM papers
+            "SELECT id, arxiv_id, title, description, submission_date, body, version
+             FROM papers
              WHERE id = $1",
             desired_id
         )
-        .fetch_one(&self.pool)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    pub async fn get_paper_by_arxiv_id(
+        &self,
+        desired_arxiv_id: &str,
+    ) -> Result<Option<models::Paper>> {
+        sqlx::query_as!(
+            models::Paper,
+            "SELECT id, arxiv_id, title, description, submission_date, body, version
+             FROM papers
+             WHERE arxiv_id = $1",
+            desired_arxiv_id
+        )
+        .fetch_optional(&self.pool)
         .await
         .map_err(|e| e.into())
     }
 
+    pub async fn get_paper_full(&self, paper: models::Paper) -> Result<models::PaperFull> {
+        let authors = self.get_paper_authors(paper.id).await?;
+        let subjects = self.get_paper_subjects(paper.id).await?;
+
+        Ok(models::PaperFull {
+            paper,
+            authors,
+            subjects,
+        })
+    }
+
     pub async fn get_papers_by_date(&self, date: chrono::NaiveDate) -> Result<Vec<models::Paper>> {
         sqlx::query_as!(
             models::Paper,
-            "SELECT * FROM papers
+            "SELECT id, arxiv_id, title, description, submission_date, body, version
+             FROM papers
              WHERE submission_date = $1",
             date
         )
@@ -65,258 +274,968 @@ impl DBConnection {
         .map_err(|e| e.into())
     }
 
-    #[allow(unused)]
-    pub async fn get_paper_authors(&self, desired_paper_id: i32) -> Result<Vec<models::Author>> {
+    /// List papers in `(submission_date, id)` order starting after `after`.
+    /// Description and body are fetched only if asked for.
+    pub async fn list_papers(
+        &self,
+        filter: &PaperFilter,
+        after: Option<(chrono::NaiveDate, models::Id)>,
+        with_description: bool,
+        with_body: bool,
+        limit: i64,
+    ) -> Result<Vec<models::PaperProjection>> {
+        let (after_date, after_id) = after.unzip();
+
         sqlx::query_as!(
-            models::Author,
-            "SELECT authors.id, authors.name
-             FROM authors
-             JOIN paper_author ON authors.id = paper_author.author_id
-             WHERE paper_author.paper_id = $1",
-            desired_paper_id
+            models::PaperProjection,
+            r#"SELECT id AS "id?", arxiv_id AS "arxiv_id?", title AS "title?",
+                      CASE WHEN $1 THEN description END AS description,
+                      submission_date AS "submission_date?",
+                      CASE WHEN $2 THEN body END AS body,
+                      version AS "version?"
+               FROM papers
+               WHERE ($3::date IS NULL OR submission_date >= $3)
+               AND ($4::date IS NULL OR submission_date <= $4)
+               AND ($5::varchar IS NULL OR EXISTS (
+                   SELECT * FROM paper_subject
+                   JOIN subjects ON subjects.id = paper_subject.subject_id
+                   WHERE paper_subject.paper_id = papers.id AND subjects.name = $5))
+               AND ($6::integer IS NULL OR EXISTS (
+                   SELECT * FROM paper_author
+                   WHERE paper_author.paper_id = papers.id AND paper_author.author_id = $6))
+               AND ($7::date IS NULL OR (submission_date, id) > ($7, $8))
+               ORDER BY submission_date, id
+               LIMIT $9"#,
+            with_description,
+            with_body,
+            filter.range.from,
+            filter.range.to,
+            filter.subject,
+            filter.author_id,
+            after_date,
+            after_id,
+            limit
         )
         .fetch_all(&self.pool)
         .await
         .map_err(|e| e.into())
     }
 
-    #[allow(unused)]
-    pub async fn paper_exists(&self, desired_arxiv_id: &str) -> Result<bool> {
-        let exists = sqlx::query_scalar!(
-            "SELECT EXISTS(SELECT * FROM papers WHERE arxiv_id = $1)",
-            desired_arxiv_id
+    /// Stream papers in the same shape they are submitted in. Rows are read
+    /// from a server side cursor `batch_size` at a time.
+    pub async fn export_papers(
+        &self,
+        filter: &PaperFilter,
+        batch_size: i64,
+    ) -> Result<impl Stream<Item = Result<Vec<NewPaperFull>>>> {
+        let mut tx = self.pool.begin().await?;
+
+        // NOTE: cursors can not be checked at compile time
+        sqlx::query(
+            "DECLARE export NO SCROLL CURSOR FOR
+             SELECT arxiv_id, title, description, submission_date, body, version,
+                    ARRAY(SELECT authors.name FROM authors
+                          JOIN paper_author ON authors.id = paper_author.author_id
+                          WHERE paper_author.paper_id = papers.id
+                          ORDER BY authors.name) AS authors,
+                    ARRAY(SELECT subjects.name FROM subjects
+                          JOIN paper_subject ON subjects.id = paper_subject.subject_id
+                          WHERE paper_subject.paper_id = papers.id
+                          ORDER BY subjects.name) AS subjects
+             FROM papers
+             WHERE ($1::date IS NULL OR submission_date >= $1)
+             AND ($2::date IS NULL OR submission_date <= $2)
+             AND ($3::varchar IS NULL OR EXISTS (
+                 SELECT * FROM paper_subject
+                 JOIN subjects ON subjects.id = paper_subject.subject_id
+                 WHERE paper_subject.paper_id = papers.id AND subjects.name = $3))
+             AND ($4::integer IS NULL OR EXISTS (
+                 SELECT * FROM paper_author
+                 WHERE paper_author.paper_id = papers.id AND paper_author.author_id = $4))
+             ORDER BY submission_date, id",
         )
-        .fetch_one(&self.pool)
-        .await?
-        .unwrap(); // NOTE(mchernigin): I am sure it returns a bool
+        .bind(filter.range.from)
+        .bind(filter.range.to)
+        .bind(&filter.subject)
+        .bind(filter.author_id)
+        .execute(&mut *tx)
+        .await?;
 
-        Ok(exists)
+        let fetch = format!("FETCH {batch_size} FROM export");
+        Ok(futures::stream::try_unfold(Some(tx), move |tx| {
+            let fetch = fetch.clone();
+            async move {
+                let Some(mut tx) = tx else {
+                    return Ok(None);
+                };
+
+                let rows = sqlx::query_as::<_, ExportRow>(&fetch)
+                    .fetch_all(&mut *tx)
+                    .await?;
+                if rows.is_empty() {
+                    tx.commit().await?;
+                    return Ok(None);
+                }
+
+                let papers = rows.into_iter().map(NewPaperFull::from).collect();
+                Ok(Some((papers, Some(tx))))
+            }
+        }))
     }
 
-    pub async fn insert_paper(
+    pub async fn get_paper_versions(
         &self,
-        new_paper: NewPaper,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    ) -> Result<models::Id> {
-        log::trace!("DB: inserting new paper {:?}", new_paper.arxiv_id);
-        sqlx::query!(
-            "INSERT INTO papers (arxiv_id, title, description, submission_date, body)
-             VALUES ($1, $2, $3, $4, $5)
-             ON CONFLICT (arxiv_id) DO NOTHING",
-            new_paper.arxiv_id,
-            new_paper.title,
-            new_paper.description,
-            new_paper.submission_date,
-            new_paper.body,
+        arxiv_id: &str,
+    ) -> Result<Vec<models::PaperVersionInfo>> {
+        sqlx::query_as!(
+            models::PaperVersionInfo,
+            r#"SELECT version AS "version!", title AS "title!", superseded_at
+               FROM (
+                   SELECT version, title, NULL::timestamp AS superseded_at
+                   FROM papers
+                   WHERE arxiv_id = $1
+                   UNION ALL
+                   SELECT paper_versions.version, paper_versions.title, superseded_at
+                   FROM paper_versions JOIN papers ON papers.id = paper_versions.paper_id
+                   WHERE papers.arxiv_id = $1
+               ) AS versions
+               ORDER BY version"#,
+            arxiv_id
         )
-        .execute(&mut **tx)
-        .await?;
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
 
-        Ok(sqlx::query_scalar!(
-            "SELECT papers.id FROM papers
-             WHERE arxiv_id = $1",
-            new_paper.arxiv_id
+    pub async fn get_paper_version(
+        &self,
+        arxiv_id: &str,
+        version: i32,
+    ) -> Result<Option<models::PaperVersion>> {
+        sqlx::query_as!(
+            models::PaperVersion,
+            r#"SELECT arxiv_id AS "arxiv_id!", version AS "version!", title AS "title!",
+                      description AS "description!", body AS "body!", superseded_at
+               FROM (
+                   SELECT arxiv_id, version, title, description, body,
+                          NULL::timestamp AS superseded_at
+                   FROM papers
+                   WHERE arxiv_id = $1 AND version = $2
+                   UNION ALL
+                   SELECT papers.arxiv_id, paper_versions.version, paper_versions.title,
+                          paper_versions.description, paper_versions.body, superseded_at
+                   FROM paper_versions JOIN papers ON papers.id = paper_versions.paper_id
+                   WHERE papers.arxiv_id = $1 AND paper_versions.version = $2
+               ) AS versions"#,
+            arxiv_id,
+            version
         )
-        .fetch_one(&mut **tx)
-        .await?)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| e.into())
     }
 
-    pub async fn insert_author(
+    pub async fn search_papers(
         &self,
-        new_author: NewAuthor,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    ) -> Result<models::Id> {
-        log::trace!("DB: inserting new author {:?}", new_author);
+        query: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<models::PaperSearchHit>)> {
+        log::trace!("DB: searching papers for {:?}", query);
 
-        sqlx::query!(
-            "INSERT INTO authors (name)
-             VALUES ($1)
-             ON CONFLICT(name) DO NOTHING",
-            new_author.name
+        let total = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM papers
+             WHERE search @@ websearch_to_tsquery('english', $1)",
+            query
         )
-        .execute(&mut **tx)
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap(); // NOTE: it always returns a number
+
+        let hits = sqlx::query_as!(
+            models::PaperSearchHit,
+            r#"SELECT id, arxiv_id, title, submission_date,
+                      ts_rank(search, query) AS "rank!",
+                      ts_headline('english', description, query,
+                                  'MaxFragments=2, MaxWords=30, MinWords=10') AS "snippet!"
+               FROM papers, websearch_to_tsquery('english', $1) query
+               WHERE search @@ query
+               ORDER BY "rank!" DESC, id
+               LIMIT $2 OFFSET $3"#,
+            query,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(sqlx::query_scalar!(
-            "SELECT authors.id FROM authors
-             WHERE name = $1",
-            new_author.name
+        Ok((total, hits))
+    }
+
+    pub async fn get_paper_authors(&self, desired_paper_id: i32) -> Result<Vec<models::Author>> {
+        sqlx::query_as!(
+            models::Author,
+            "SELECT authors.id, authors.name
+             FROM authors
+             JOIN paper_author ON authors.id = paper_author.author_id
+             WHERE paper_author.paper_id = $1",
+            desired_paper_id
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    pub async fn get_paper_subjects(&self, desired_paper_id: i32) -> Result<Vec<models::Subject>> {
+        sqlx::query_as!(
+            models::Subject,
+            "SELECT subjects.id, subjects.name
+             FROM subjects
+             JOIN paper_subject ON subjects.id = paper_subject.subject_id
+             WHERE paper_subject.paper_id = $1",
+            desired_paper_id
         )
-        .fetch_one(&mut **tx)
-        .await?)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
     }
 
-    pub async fn insert_subject(
+    pub async fn get_authors(
         &self,
-        new_subject: NewSubject,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    ) -> Result<models::Id> {
-        log::trace!("DB: inserting new subject {:?}", new_subject.name);
+        prefix: &str,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<models::AuthorStats>> {
+        let pattern = format!("{}%", escape_like(&prefix.to_lowercase()));
 
-        sqlx::query!(
-            "INSERT INTO subjects (name)
-             VALUES ($1)
-             ON CONFLICT(name) DO NOTHING",
-            new_subject.name
+        sqlx::query_as!(
+            models::AuthorStats,
+            r#"SELECT id, name,
+                      (SELECT COUNT(*) FROM paper_author
+                       WHERE paper_author.author_id = authors.id) AS "paper_count!"
+               FROM authors
+               WHERE lower(name) LIKE $1
+               ORDER BY lower(name), id
+               LIMIT $2 OFFSET $3"#,
+            pattern,
+            limit,
+            offset
         )
-        .execute(&mut **tx)
-        .await?;
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
 
-        Ok(sqlx::query_scalar!(
-            "SELECT subjects.id FROM subjects
-             WHERE name = $1",
-            new_subject.name
+    pub async fn author_exists(&self, desired_id: i32) -> Result<bool> {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT * FROM authors WHERE id = $1)",
+            desired_id
         )
-        .fetch_one(&mut **tx)
-        .await?)
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap(); // NOTE: it always returns a bool
+
+        Ok(exists)
     }
 
-    pub async fn set_paper_author(
+    pub async fn get_author_papers(
         &self,
-        paper_id: models::Id,
-        author_id: models::Id,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    ) -> Result<()> {
-        log::trace!(
-            "DB: inserting author {:?} for paper {:?}",
+        author_id: i32,
+        range: DateRange,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<models::PaperSummary>)> {
+        let total = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM papers
+             JOIN paper_author ON papers.id = paper_author.paper_id
+             WHERE paper_author.author_id = $1
+             AND ($2::date IS NULL OR submission_date >= $2)
+             AND ($3::date IS NULL OR submission_date <= $3)",
             author_id,
-            paper_id
-        );
-        sqlx::query!(
-            "INSERT INTO paper_author (paper_id, author_id)
-             VALUES ($1, $2)
-             ON CONFLICT ON CONSTRAINT paper_author_pkey DO NOTHING",
-            paper_id,
-            author_id
+            range.from,
+            range.to
         )
-        .execute(&mut **tx)
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap(); // NOTE: it always returns a number
+
+        let papers = sqlx::query_as!(
+            models::PaperSummary,
+            "SELECT id, arxiv_id, title, description, submission_date, version
+             FROM papers
+             JOIN paper_author ON papers.id = paper_author.paper_id
+             WHERE paper_author.author_id = $1
+             AND ($2::date IS NULL OR submission_date >= $2)
+             AND ($3::date IS NULL OR submission_date <= $3)
+             ORDER BY submission_date DESC, id DESC
+             LIMIT $4 OFFSET $5",
+            author_id,
+            range.from,
+            range.to,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(())
+        Ok((total, papers))
+    }
+
+    pub async fn get_subjects(&self) -> Result<Vec<models::SubjectStats>> {
+        sqlx::query_as!(
+            models::SubjectStats,
+            r#"SELECT id, name,
+                      (SELECT COUNT(*) FROM paper_subject
+                       WHERE paper_subject.subject_id = subjects.id) AS "paper_count!"
+               FROM subjects
+               ORDER BY name"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    pub async fn subject_exists(&self, desired_name: &str) -> Result<bool> {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT * FROM subjects WHERE name = $1)",
+            desired_name
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap(); // NOTE: it always returns a bool
+
+        Ok(exists)
     }
 
-    pub async fn set_paper_subject(
+    pub async fn get_subject_papers(
         &self,
-        paper_id: models::Id,
-        subject_id: models::Id,
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
-    ) -> Result<()> {
-        log::trace!(
-            "DB: inserting subject {:?} for paper {:?}",
-            subject_id,
-            paper_id
-        );
-        sqlx::query!(
-            "INSERT INTO paper_subject (paper_id, subject_id)
-             VALUES ($1, $2)
-             ON CONFLICT ON CONSTRAINT paper_subject_pkey DO NOTHING",
-            paper_id,
-            subject_id,
+        subject: &str,
+        range: DateRange,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<models::PaperSummary>)> {
+        let total = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM papers
+             JOIN paper_subject ON papers.id = paper_subject.paper_id
+             JOIN subjects ON subjects.id = paper_subject.subject_id
+             WHERE subjects.name = $1
+             AND ($2::date IS NULL OR submission_date >= $2)
+             AND ($3::date IS NULL OR submission_date <= $3)",
+            subject,
+            range.from,
+            range.to
         )
-        .execute(&mut **tx)
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap(); // NOTE: it always returns a number
+
+        let papers = sqlx::query_as!(
+            models::PaperSummary,
+            "SELECT papers.id, arxiv_id, title, description, submission_date, version
+             FROM papers
+             JOIN paper_subject ON papers.id = paper_subject.paper_id
+             JOIN subjects ON subjects.id = paper_subject.subject_id
+             WHERE subjects.name = $1
+             AND ($2::date IS NULL OR submission_date >= $2)
+             AND ($3::date IS NULL OR submission_date <= $3)
+             ORDER BY submission_date DESC, papers.id DESC
+             LIMIT $4 OFFSET $5",
+            subject,
+            range.from,
+            range.to,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
         .await?;
 
-        Ok(())
+        Ok((total, papers))
+    }
+
+    #[allow(unused)]
+    pub async fn paper_exists(&self, desired_arxiv_id: &str) -> Result<bool> {
+        let exists = sqlx::query_scalar!(
+            "SELECT EXISTS(SELECT * FROM papers WHERE arxiv_id = $1)",
+            desired_arxiv_id
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap(); // NOTE(mchernigin): I am sure it returns a bool
+
+        Ok(exists)
     }
 
+    /// Insert papers with their authors and subjects using a fixed number of
+    /// queries per batch. Papers which are already archived are skipped.
     pub async fn insert_papers_full(
         &self,
-        papers_full: Vec<NewPaperFull>,
+        mut papers_full: Vec<NewPaperFull>,
+        report: &mut models::IngestionReport,
         tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
     ) -> Result<()> {
+        log::trace!("DB: inserting batch of {} papers", papers_full.len());
+
+        let mut arxiv_ids = Vec::with_capacity(papers_full.len());
+        let mut titles = Vec::with_capacity(papers_full.len());
+        let mut descriptions = Vec::with_capacity(papers_full.len());
+        let mut submission_dates = Vec::with_capacity(papers_full.len());
+        let mut bodies = Vec::with_capacity(papers_full.len());
+        let mut versions = Vec::with_capacity(papers_full.len());
+        let mut paper_authors = Vec::new();
+        let mut paper_subjects = Vec::new();
+
+        // NOTE: sorted so concurrent submissions lock rows in the same order, sort is
+        // stable, so the first of repeated papers is kept
+        papers_full.sort_by(|a, b| a.arxiv_id.cmp(&b.arxiv_id));
+
+        let mut seen = HashSet::new();
         for paper_full in papers_full {
-            if self.paper_exists(&paper_full.arxiv_id).await? {
+            if !seen.insert(paper_full.arxiv_id.clone()) {
                 log::warn!(
-                    "DB: paper {:?} already exists in archive, skipping",
+                    "DB: paper {:?} is submitted twice, skipping",
                     paper_full.arxiv_id
                 );
-                return Ok(());
+                report.rejected.push(models::RejectedPaper {
+                    arxiv_id: Some(paper_full.arxiv_id),
+                    reason: "Paper is submitted twice".to_string(),
+                });
+                continue;
             }
 
-            let paper = NewPaper {
-                arxiv_id: paper_full.arxiv_id,
-                title: paper_full.title,
-                description: paper_full.description,
-                submission_date: paper_full.submission_date,
-                body: paper_full.body,
-            };
-
-            let paper_id = self.insert_paper(paper, &mut *tx).await?;
-
             for author in paper_full.authors {
-                let author_id = self.insert_author(author, &mut *tx).await?;
-                self.set_paper_author(paper_id, author_id, &mut *tx).await?;
+                paper_authors.push((paper_full.arxiv_id.clone(), author.name));
             }
-
             for subject in paper_full.subjects {
-                let subject_id = self.insert_subject(subject, &mut *tx).await?;
-                self.set_paper_subject(paper_id, subject_id, &mut *tx)
-                    .await?;
+                paper_subjects.push((paper_full.arxiv_id.clone(), subject.name));
             }
+
+            arxiv_ids.push(paper_full.arxiv_id);
+            titles.push(paper_full.title);
+            descriptions.push(paper_full.description);
+            submission_dates.push(paper_full.submission_date);
+            bodies.push(paper_full.body);
+            versions.push(paper_full.version);
         }
 
-        Ok(())
-    }
+        let mut paper_ids = sqlx::query!(
+            r#"INSERT INTO papers (arxiv_id, title, description, submission_date, body, version)
+               SELECT *
+               FROM UNNEST ($1::varchar[], $2::varchar[], $3::text[], $4::date[], $5::text[],
+                            $6::integer[])
+               ON CONFLICT (arxiv_id) DO NOTHING
+               RETURNING id, arxiv_id"#,
+            &arxiv_ids[..],
+            &titles[..],
+            &descriptions[..],
+            &submission_dates[..],
+            &bodies[..],
+            &versions[..],
+        )
+        .fetch_all(&mut **tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.arxiv_id, row.id))
+        .collect::<HashMap<_, _>>();
+        report.inserted += paper_ids.len();
 
-    pub async fn get_next_task(&self) -> Result<Option<models::Task>> {
-        let mut tx = self.pool.begin().await?;
+        // Papers which are already archived get a new revision if a newer version with
+        // different content is submitted. Different content of the archived version is
+        // reported as a conflict, everything else is skipped
+        let mut existing = (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (i, arxiv_id) in arxiv_ids.into_iter().enumerate() {
+            if !paper_ids.contains_key(&arxiv_id) {
+                existing.0.push(arxiv_id);
+                existing.1.push(std::mem::take(&mut titles[i]));
+                existing.2.push(std::mem::take(&mut descriptions[i]));
+                existing.3.push(std::mem::take(&mut bodies[i]));
+                existing.4.push(versions[i]);
+            }
+        }
 
-        let res = sqlx::query_as!(
-            models::Task,
-            "SELECT submission_date, status as \"status: _\", processing_start, processing_end
-             FROM tasks WHERE status = $1
-             LIMIT 1
-             FOR UPDATE",
-            models::Status::Idle as models::Status,
+        let rows = sqlx::query!(
+            r#"WITH input AS (
+                   SELECT *
+                   FROM UNNEST ($1::varchar[], $2::varchar[], $3::text[], $4::text[], $5::integer[])
+                   AS input (arxiv_id, title, description, body, version)
+               ),
+               old AS (
+                   SELECT papers.id, papers.version, papers.title, papers.description, papers.body
+                   FROM papers JOIN input USING (arxiv_id)
+                   WHERE input.version > papers.version
+                     AND (papers.title, papers.description, papers.body)
+                         IS DISTINCT FROM (input.title, input.description, input.body)
+                   ORDER BY papers.arxiv_id
+                   FOR UPDATE OF papers
+               ),
+               history AS (
+                   INSERT INTO paper_versions (paper_id, version, title, description, body,
+                                               superseded_at)
+                   SELECT id, version, title, description, body, $6 FROM old
+               ),
+               updated AS (
+                   UPDATE papers
+                   SET title = input.title, description = input.description, body = input.body,
+                       version = input.version
+                   FROM input
+                   WHERE papers.arxiv_id = input.arxiv_id AND papers.id IN (SELECT id FROM old)
+                   RETURNING papers.id, papers.arxiv_id, papers.version
+               )
+               SELECT id AS "id!", arxiv_id AS "arxiv_id!", version AS "version!",
+                      true AS "updated!"
+               FROM updated
+               UNION ALL
+               SELECT papers.id, papers.arxiv_id, papers.version, false
+               FROM papers JOIN input USING (arxiv_id)
+               WHERE input.version = papers.version
+                 AND (papers.title, papers.description, papers.body)
+                     IS DISTINCT FROM (input.title, input.description, input.body)"#,
+            &existing.0[..],
+            &existing.1[..],
+            &existing.2[..],
+            &existing.3[..],
+            &existing.4[..],
+            chrono::Utc::now().naive_utc(),
         )
-        .fetch_optional(&mut *tx)
+        .fetch_all(&mut **tx)
         .await?;
 
-        if let Some(task) = &res {
-            log::trace!("DB: given {:?} as next task", task.submission_date);
-
-            sqlx::query!(
-                "UPDATE tasks
-                 SET status = $1, processing_start = $2
-                 WHERE submission_date = $3",
-                models::Status::Processing as models::Status,
-                chrono::Utc::now().naive_utc(),
-                task.submission_date
-            )
-            .execute(&mut *tx)
-            .await?;
-        } else {
-            log::trace!("DB: next task requested but queue is empty");
+        let mut updated_ids = Vec::new();
+        let mut conflicts = HashSet::new();
+        for row in rows {
+            if row.updated {
+                log::info!(
+                    "DB: paper {:?} updated to version {}",
+                    row.arxiv_id,
+                    row.version
+                );
+                updated_ids.push(row.id);
+                paper_ids.insert(row.arxiv_id, row.id);
+            } else {
+                conflicts.insert(row.arxiv_id);
+            }
         }
+        report.updated += updated_ids.len();
 
-        tx.commit().await?;
+        for arxiv_id in existing.0 {
+            if conflicts.contains(&arxiv_id) {
+                log::warn!(
+                    "DB: paper {:?} is submitted with different content but the same version, \
+                     skipping",
+                    arxiv_id
+                );
+                report.unchanged_version_conflicts.push(arxiv_id);
+            } else if !paper_ids.contains_key(&arxiv_id) {
+                log::warn!(
+                    "DB: paper {:?} already exists in archive, skipping",
+                    arxiv_id
+                );
+                report.skipped.push(arxiv_id);
+            }
+        }
 
-        Ok(res)
+        // Authors and subjects are linked only to papers inserted or updated just now,
+        // updated papers lose the old ones
+        sqlx::query!(
+            "DELETE FROM paper_author WHERE paper_id = ANY($1)",
+            &updated_ids[..]
+        )
+        .execute(&mut **tx)
+        .await?;
+        sqlx::query!(
+            "DELETE FROM paper_subject WHERE paper_id = ANY($1)",
+            &updated_ids[..]
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        paper_authors.retain(|(arxiv_id, _)| paper_ids.contains_key(arxiv_id));
+        paper_subjects.retain(|(arxiv_id, _)| paper_ids.contains_key(arxiv_id));
+
+        let author_ids = self
+            .insert_authors(paper_authors.iter().map(|(_, name)| name), &mut *tx)
+            .await?;
+        let (paper_id_list, author_id_list): (Vec<_>, Vec<_>) = paper_authors
+            .iter()
+            .map(|(arxiv_id, name)| (paper_ids[arxiv_id], author_ids[name]))
+            .unzip();
+        sqlx::query!(
+            r#"INSERT INTO paper_author (paper_id, author_id)
+               SELECT *
+               FROM UNNEST ($1::integer[], $2::integer[])
+               ON CONFLICT ON CONSTRAINT paper_author_pkey DO NOTHING"#,
+            &paper_id_list[..],
+            &author_id_list[..],
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        let subject_ids = self
+            .insert_subjects(paper_subjects.iter().map(|(_, name)| name), &mut *tx)
+            .await?;
+        let (paper_id_list, subject_id_list): (Vec<_>, Vec<_>) = paper_subjects
+            .iter()
+            .map(|(arxiv_id, name)| (paper_ids[arxiv_id], subject_ids[name]))
+            .unzip();
+        sqlx::query!(
+            r#"INSERT INTO paper_subject (paper_id, subject_id)
+               SELECT *
+               FROM UNNEST ($1::integer[], $2::integer[])
+               ON CONFLICT ON CONSTRAINT paper_subject_pkey DO NOTHING"#,
+            &paper_id_list[..],
+            &subject_id_list[..],
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Insert missing authors and return ids of all given ones by name.
+    async fn insert_authors(
+        &self,
+        names: impl Iterator<Item = &String>,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<HashMap<String, models::Id>> {
+        // NOTE: sorted so concurrent submissions lock rows in the same order
+        let names = names.cloned().collect::<BTreeSet<_>>();
+        let names = names.into_iter().collect::<Vec<_>>();
+
+        sqlx::query!(
+            r#"INSERT INTO authors (name)
+               SELECT * FROM UNNEST ($1::varchar[])
+               ON CONFLICT (name) DO NOTHING"#,
+            &names[..],
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(sqlx::query!(
+            "SELECT id, name FROM authors
+             WHERE name = ANY($1)",
+            &names[..],
+        )
+        .fetch_all(&mut **tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.name, row.id))
+        .collect())
+    }
+
+    /// Insert missing subjects and return ids of all given ones by name.
+    async fn insert_subjects(
+        &self,
+        names: impl Iterator<Item = &String>,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<HashMap<String, models::Id>> {
+        // NOTE: sorted so concurrent submissions lock rows in the same order
+        let names = names.cloned().collect::<BTreeSet<_>>();
+        let names = names.into_iter().collect::<Vec<_>>();
+
+        sqlx::query!(
+            r#"INSERT INTO subjects (name)
+               SELECT * FROM UNNEST ($1::varchar[])
+               ON CONFLICT (name) DO NOTHING"#,
+            &names[..],
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(sqlx::query!(
+            "SELECT id, name FROM subjects
+             WHERE name = ANY($1)",
+            &names[..],
+        )
+        .fetch_all(&mut **tx)
+        .await?
+        .into_iter()
+        .map(|row| (row.name, row.id))
+        .collect())
+    }
+
+    /// Lease up to `count` idle tasks with the highest priority, ties are
+    /// broken by date in given order. Rows locked by concurrent claims are
+    /// skipped, so scrapers do not wait for each other.
+    pub async fn get_next_tasks(
+        &self,
+        lease_seconds: u64,
+        worker: &str,
+        order: DispatchOrder,
+        count: i64,
+    ) -> Result<Vec<models::Task>> {
+        let mut tx = self.pool.begin().await?;
+
+        self.touch_worker(worker, &mut tx).await?;
+
+        let now = chrono::Utc::now().naive_utc();
+        let newest_first = matches!(order, DispatchOrder::NewestFirst);
+        let mut tasks = sqlx::query_as!(
+            models::Task,
+            r#"WITH next AS (
+                   SELECT submission_date, category
+                   FROM tasks WHERE status = $1
+                   AND (retry_after IS NULL OR retry_after <= $2)
+                   ORDER BY priority DESC,
+                            CASE WHEN $3 THEN submission_date END DESC,
+                            submission_date ASC,
+                            category ASC
+                   LIMIT $4
+                   FOR UPDATE SKIP LOCKED
+               )
+               UPDATE tasks
+               SET status = $5, processing_start = $2,
+                   lease_id = gen_random_uuid(), lease_expires_at = $6, worker = $7,
+                   attempts = attempts + 1, retry_after = NULL
+               FROM next
+               WHERE tasks.submission_date = next.submission_date
+               AND tasks.category = next.category
+               RETURNING tasks.submission_date, NULLIF(tasks.category, '') AS category,
+                         status as "status: _", processing_start, processing_end, lease_id,
+                         lease_expires_at, worker, submitted_by, attempts, last_error,
+                         retry_after, priority, submission_key"#,
+            models::Status::Idle as models::Status,
+            now,
+            newest_first,
+            count,
+            models::Status::Processing as models::Status,
+            now + lease_duration(lease_seconds),
+            worker,
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        // NOTE: UPDATE does not keep order of the subquery
+        tasks.sort_by(|a, b| {
+            let by_date = if newest_first {
+                b.submission_date.cmp(&a.submission_date)
+            } else {
+                a.submission_date.cmp(&b.submission_date)
+            };
+            b.priority
+                .cmp(&a.priority)
+                .then(by_date)
+                .then_with(|| a.category.cmp(&b.category))
+        });
+
+        if tasks.is_empty() {
+            log::trace!("DB: next task requested but queue is empty");
+        } else {
+            log::trace!(
+                "DB: given {:?} as next tasks to {:?}",
+                tasks
+                    .iter()
+                    .map(|task| (task.submission_date, task.category.as_deref()))
+                    .collect::<Vec<_>>(),
+                worker
+            );
+        }
+
+        Ok(tasks)
+    }
+
+    pub async fn extend_lease(
+        &self,
+        submission_date: chrono::NaiveDate,
+        lease_id: uuid::Uuid,
+        lease_seconds: u64,
+    ) -> Result<models::TaskLease> {
+        log::trace!("DB: extending lease for task {:?}", submission_date);
+
+        let mut tx = self.pool.begin().await?;
+
+        let lease = sqlx::query!(
+            r#"UPDATE tasks
+               SET lease_expires_at = $1
+               WHERE submission_date = $2 AND status = $3 AND lease_id = $4
+               RETURNING NULLIF(category, '') AS category,
+                         lease_expires_at AS "lease_expires_at!", worker"#,
+            chrono::Utc::now().naive_utc() + lease_duration(lease_seconds),
+            submission_date,
+            models::Status::Processing as models::Status,
+            lease_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::LeaseMismatch(submission_date))?;
+
+        if let Some(worker) = lease.worker {
+            self.touch_worker(&worker, &mut tx).await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(models::TaskLease {
+            submission_date,
+            category: lease.category,
+            lease_id,
+            lease_expires_at: lease.lease_expires_at,
+        })
     }
 
-    pub async fn insert_task(&self, new_tasks: Vec<models::NewTask>) -> Result<()> {
+    pub async fn touch_worker(
+        &self,
+        worker: &str,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO workers (name, last_seen)
+             VALUES ($1, $2)
+             ON CONFLICT (name) DO UPDATE SET last_seen = EXCLUDED.last_seen",
+            worker,
+            chrono::Utc::now().naive_utc()
+        )
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_workers(&self) -> Result<Vec<models::Worker>> {
+        sqlx::query_as!(
+            models::Worker,
+            r#"SELECT name, last_seen, completed, failed,
+                      (SELECT COUNT(*) FROM tasks
+                       WHERE tasks.worker = workers.name AND tasks.status = $1) AS "tasks_in_flight!"
+               FROM workers
+               ORDER BY last_seen DESC"#,
+            models::Status::Processing as models::Status
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Insert new idle tasks. If priority is given, it is also set on already
+    /// existing idle tasks for the same days and categories.
+    pub async fn insert_task(
+        &self,
+        new_tasks: Vec<models::NewTask>,
+        priority: Option<i32>,
+    ) -> Result<()> {
         log::trace!("DB: inserting new tasks ({})", new_tasks.len());
 
-        let submission_dates = new_tasks
+        let (submission_dates, categories): (Vec<_>, Vec<_>) = new_tasks
             .into_iter()
-            .map(|models::NewTask { submission_date }| submission_date)
-            .collect::<Vec<_>>();
+            .map(|task| (task.submission_date, task.category.unwrap_or_default()))
+            .unzip();
         let statuses = vec![models::Status::Idle; submission_dates.len()];
 
-        sqlx::query!(
-            r#"INSERT INTO tasks (submission_date, status)
-               SELECT *
-               FROM UNNEST ($1::date[], $2::status[])
-               ON CONFLICT (submission_date) DO NOTHING"#,
+        let mut tx = self.pool.begin().await?;
+        let inserted = sqlx::query!(
+            r#"INSERT INTO tasks (submission_date, category, status, priority)
+               SELECT *, COALESCE($4::int4, 0)
+               FROM UNNEST ($1::date[], $2::varchar[], $3::status[])
+               ON CONFLICT (submission_date, category) DO UPDATE
+               SET priority = EXCLUDED.priority
+               WHERE $4::int4 IS NOT NULL AND tasks.status = $5"#,
             &submission_dates[..],
+            &categories[..],
             &statuses[..] as &[models::Status],
+            priority,
+            models::Status::Idle as models::Status,
         )
-        .execute(&self.pool)
-        .await?;
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if inserted > 0 {
+            self.notify_tasks_available(&mut tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Create whole day tasks for the given days which have no tasks at all.
+    /// Returns number of created tasks.
+    pub async fn enqueue_days(&self, submission_dates: &[chrono::NaiveDate]) -> Result<u64> {
+        log::trace!("DB: enqueueing days {:?}", submission_dates);
+
+        let mut tx = self.pool.begin().await?;
+        let created = sqlx::query!(
+            "INSERT INTO tasks (submission_date, status)
+             SELECT day, $2 FROM UNNEST ($1::date[]) AS day
+             WHERE NOT EXISTS (SELECT 1 FROM tasks WHERE submission_date = day)
+             ON CONFLICT (submission_date, category) DO NOTHING",
+            submission_dates,
+            models::Status::Idle as models::Status,
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if created > 0 {
+            self.notify_tasks_available(&mut tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(created)
+    }
+
+    /// Put whole day tasks for the given days to the queue, creating missing
+    /// ones and scraping done or failed ones again. Days split by categories
+    /// are left alone. Returns number of queued tasks.
+    pub async fn requeue_tasks(&self, submission_dates: &[chrono::NaiveDate]) -> Result<u64> {
+        log::trace!("DB: requeueing tasks {:?}", submission_dates);
+
+        let mut tx = self.pool.begin().await?;
+        let queued = sqlx::query!(
+            "INSERT INTO tasks (submission_date, status)
+             SELECT day, $2 FROM UNNEST ($1::date[]) AS day
+             WHERE NOT EXISTS (SELECT 1 FROM tasks WHERE submission_date = day AND category <> '')
+             OR EXISTS (SELECT 1 FROM tasks WHERE submission_date = day AND category = '')
+             ON CONFLICT (submission_date, category) DO UPDATE
+             SET status = EXCLUDED.status, processing_start = NULL, processing_end = NULL,
+                 lease_id = NULL, lease_expires_at = NULL, attempts = 0,
+                 last_error = NULL, retry_after = NULL,
+                 submission_key = NULL, submission_report = NULL
+             WHERE tasks.status = ANY($3)",
+            submission_dates,
+            models::Status::Idle as models::Status,
+            &[models::Status::Done, models::Status::Failed] as &[models::Status],
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        if queued > 0 {
+            self.notify_tasks_available(&mut tx).await?;
+        }
+        tx.commit().await?;
+
+        Ok(queued)
+    }
+
+    /// Wake up requests waiting for tasks once the transaction is committed.
+    async fn notify_tasks_available(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<()> {
+        sqlx::query!("SELECT pg_notify($1, '')", TASKS_CHANNEL)
+            .execute(&mut **tx)
+            .await?;
 
         Ok(())
     }
 
+    /// Listen for notifications about available tasks and pass them to
+    /// waiting requests. Never returns, lost connection is reestablished.
+    pub async fn listen_for_tasks(&self, waiters: &TaskWaiters) -> Result<()> {
+        let mut listener = sqlx::postgres::PgListener::connect_with(&self.pool).await?;
+        listener.listen(TASKS_CHANNEL).await?;
+
+        loop {
+            match listener.recv().await {
+                Ok(_) => waiters.0.notify_waiters(),
+                Err(e) => {
+                    log::error!("DB: lost tasks notifications: {e}");
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
     pub async fn get_tasks_stats(&self) -> Result<models::TasksStats> {
         let mut tx = self.pool.begin().await?;
 
@@ -347,27 +1266,438 @@ impl DBConnection {
         .await?
         .unwrap(); // NOTE(mchernigin): I am sure it returns a number
 
+        let failed = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM tasks
+             WHERE status = $1",
+            models::Status::Failed as models::Status
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap(); // NOTE: it always returns a number
+
+        let cancelled = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM tasks
+             WHERE status = $1",
+            models::Status::Cancelled as models::Status
+        )
+        .fetch_one(&mut *tx)
+        .await?
+        .unwrap(); // NOTE: it always returns a number
+
         tx.commit().await?;
 
         Ok(models::TasksStats {
             idle,
             processing,
             done,
+            failed,
+            cancelled,
         })
     }
 
-    pub async fn submit_task(&self, submission: models::TaskSubmission) -> Result<()> {
+    pub async fn get_task(
+        &self,
+        submission_date: chrono::NaiveDate,
+        category: Option<&str>,
+    ) -> Result<Option<models::Task>> {
+        sqlx::query_as!(
+            models::Task,
+            r#"SELECT submission_date, NULLIF(category, '') AS category, status as "status: _",
+                      processing_start, processing_end, lease_id, lease_expires_at, worker,
+                      submitted_by, attempts, last_error, retry_after, priority, submission_key
+               FROM tasks
+               WHERE submission_date = $1 AND category = COALESCE($2, '')"#,
+            submission_date,
+            category
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    pub async fn list_tasks(
+        &self,
+        status: Option<models::Status>,
+        category: Option<&str>,
+        range: &DateRange,
+        limit: i64,
+        offset: i64,
+    ) -> Result<(i64, Vec<models::Task>)> {
+        let total = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM tasks
+             WHERE ($1::status IS NULL OR status = $1)
+             AND ($2::date IS NULL OR submission_date >= $2)
+             AND ($3::date IS NULL OR submission_date <= $3)
+             AND ($4::varchar IS NULL OR category = $4)",
+            status as Option<models::Status>,
+            range.from,
+            range.to,
+            category
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .unwrap(); // NOTE: it always returns a number
+
+        let tasks = sqlx::query_as!(
+            models::Task,
+            r#"SELECT submission_date, NULLIF(category, '') AS category, status as "status: _",
+                      processing_start, processing_end, lease_id, lease_expires_at, worker,
+                      submitted_by, attempts, last_error, retry_after, priority, submission_key
+               FROM tasks
+               WHERE ($1::status IS NULL OR status = $1)
+               AND ($2::date IS NULL OR submission_date >= $2)
+               AND ($3::date IS NULL OR submission_date <= $3)
+               AND ($4::varchar IS NULL OR category = $4)
+               ORDER BY submission_date, category
+               LIMIT $5 OFFSET $6"#,
+            status as Option<models::Status>,
+            range.from,
+            range.to,
+            category,
+            limit,
+            offset
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok((total, tasks))
+    }
+
+    /// Put finished, failed or cancelled task back to the queue as if it was
+    /// just created. Returns None if there is no such task.
+    pub async fn reset_task(
+        &self,
+        submission_date: chrono::NaiveDate,
+        category: Option<&str>,
+    ) -> Result<Option<models::Task>> {
+        log::trace!("DB: resetting task {:?} {:?}", submission_date, category);
+
+        let mut tx = self.pool.begin().await?;
+
+        let status = sqlx::query_scalar!(
+            r#"SELECT status AS "status: models::Status" FROM tasks
+               WHERE submission_date = $1 AND category = COALESCE($2, '')
+               FOR UPDATE"#,
+            submission_date,
+            category
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        match status {
+            None => return Ok(None),
+            Some(models::Status::Done | models::Status::Failed | models::Status::Cancelled) => {}
+            Some(status) => {
+                return Err(Error::InvalidTransition {
+                    submission_date,
+                    status,
+                    action: "reset",
+                })
+            }
+        }
+
+        let task = sqlx::query_as!(
+            models::Task,
+            r#"UPDATE tasks
+               SET status = $1, processing_start = NULL, processing_end = NULL,
+                   lease_id = NULL, lease_expires_at = NULL, attempts = 0,
+                   last_error = NULL, retry_after = NULL,
+                   submission_key = NULL, submission_report = NULL
+               WHERE submission_date = $2 AND category = COALESCE($3, '')
+               RETURNING submission_date, NULLIF(category, '') AS category, status as "status: _",
+                         processing_start, processing_end, lease_id, lease_expires_at, worker,
+                         submitted_by, attempts, last_error, retry_after, priority, submission_key"#,
+            models::Status::Idle as models::Status,
+            submission_date,
+            category
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.notify_tasks_available(&mut tx).await?;
+        tx.commit().await?;
+
+        Ok(Some(task))
+    }
+
+    /// Cancel all unfinished tasks in range, scrapers holding a lease on them
+    /// will not be able to submit.
+    pub async fn cancel_tasks(&self, range: &DateRange) -> Result<models::TaskRangeReport> {
+        log::trace!("DB: cancelling tasks in {:?}", range);
+
+        let mut tx = self.pool.begin().await?;
+
+        let changed = sqlx::query_as!(
+            models::TaskKey,
+            r#"UPDATE tasks
+               SET status = $1, lease_id = NULL, lease_expires_at = NULL, retry_after = NULL
+               WHERE status = ANY($2)
+               AND ($3::date IS NULL OR submission_date >= $3)
+               AND ($4::date IS NULL OR submission_date <= $4)
+               RETURNING submission_date, NULLIF(category, '') AS category"#,
+            models::Status::Cancelled as models::Status,
+            &[
+                models::Status::Idle,
+                models::Status::Processing,
+                models::Status::Failed
+            ] as &[models::Status],
+            range.from,
+            range.to
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let skipped = self
+            .get_skipped_tasks(range, &[models::Status::Cancelled], &mut tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(models::TaskRangeReport { changed, skipped })
+    }
+
+    /// Delete tasks in range, except the ones being processed right now.
+    pub async fn delete_tasks(&self, range: &DateRange) -> Result<models::TaskRangeReport> {
+        log::trace!("DB: deleting tasks in {:?}", range);
+
+        let mut tx = self.pool.begin().await?;
+
+        let changed = sqlx::query_as!(
+            models::TaskKey,
+            r#"DELETE FROM tasks
+               WHERE status <> $1
+               AND ($2::date IS NULL OR submission_date >= $2)
+               AND ($3::date IS NULL OR submission_date <= $3)
+               RETURNING submission_date, NULLIF(category, '') AS category"#,
+            models::Status::Processing as models::Status,
+            range.from,
+            range.to
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let skipped = self.get_skipped_tasks(range, &[], &mut tx).await?;
+        tx.commit().await?;
+
+        Ok(models::TaskRangeReport { changed, skipped })
+    }
+
+    /// Delete single task unless it is being processed right now. Returns None
+    /// if there is no such task.
+    pub async fn delete_task(
+        &self,
+        submission_date: chrono::NaiveDate,
+        category: Option<&str>,
+    ) -> Result<Option<()>> {
+        log::trace!("DB: deleting task {:?} {:?}", submission_date, category);
+
+        let mut tx = self.pool.begin().await?;
+
+        let status = sqlx::query_scalar!(
+            r#"SELECT status AS "status: models::Status" FROM tasks
+               WHERE submission_date = $1 AND category = COALESCE($2, '')
+               FOR UPDATE"#,
+            submission_date,
+            category
+        )
+        .fetch_optional(&mut *tx)
+        .await?;
+        match status {
+            None => return Ok(None),
+            Some(models::Status::Processing) => {
+                return Err(Error::InvalidTransition {
+                    submission_date,
+                    status: models::Status::Processing,
+                    action: "deleted",
+                })
+            }
+            Some(_) => {}
+        }
+
+        sqlx::query!(
+            "DELETE FROM tasks
+             WHERE submission_date = $1 AND category = COALESCE($2, '')",
+            submission_date,
+            category
+        )
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(()))
+    }
+
+    /// Number of papers and status of the whole day task for every day in
+    /// range, including days with neither of them.
+    pub async fn get_coverage(
+        &self,
+        from: chrono::NaiveDate,
+        to: chrono::NaiveDate,
+    ) -> Result<Vec<models::DayCoverage>> {
+        // NOTE: days split by categories take status of the category task which is
+        // the furthest from being done
+        let days = sqlx::query!(
+            r#"SELECT day::date AS "submission_date!",
+                      COALESCE(tasks.status, split.status) AS "status?: models::Status",
+                      tasks.status IS NULL AND split.status IS NOT NULL AS "split!",
+                      (SELECT COUNT(*) FROM papers
+                       WHERE papers.submission_date = day::date) AS "paper_count!"
+               FROM generate_series($1::date, $2::date, interval '1 day') AS day
+               LEFT JOIN tasks ON tasks.submission_date = day::date AND tasks.category = ''
+               LEFT JOIN LATERAL (
+                   SELECT status FROM tasks AS category_tasks
+                   WHERE category_tasks.submission_date = day::date
+                   AND category_tasks.category <> ''
+                   ORDER BY array_position(
+                       ARRAY['processing', 'idle', 'failed', 'cancelled', 'done']::status[], status)
+                   LIMIT 1
+               ) AS split ON true
+               ORDER BY day"#,
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(days
+            .into_iter()
+            .map(|day| models::DayCoverage {
+                submission_date: day.submission_date,
+                status: day.status,
+                split: day.split,
+                paper_count: day.paper_count,
+                expected_count: None,
+                missing: false,
+                low: false,
+            })
+            .collect())
+    }
+
+    /// Tasks in range which were left behind by a range operation, that is
+    /// everything remaining except the ones in the target status.
+    async fn get_skipped_tasks(
+        &self,
+        range: &DateRange,
+        target: &[models::Status],
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Vec<models::SkippedTask>> {
+        sqlx::query_as!(
+            models::SkippedTask,
+            r#"SELECT submission_date, NULLIF(category, '') AS category, status AS "status: _"
+               FROM tasks
+               WHERE NOT (status = ANY($1))
+               AND ($2::date IS NULL OR submission_date >= $2)
+               AND ($3::date IS NULL OR submission_date <= $3)
+               ORDER BY submission_date, category"#,
+            target as &[models::Status],
+            range.from,
+            range.to
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Task which is processed or was already submitted under the lease,
+    /// without locking it.
+    pub async fn get_leased_task(
+        &self,
+        submission_date: chrono::NaiveDate,
+        lease_id: uuid::Uuid,
+    ) -> Result<LeasedTask> {
+        sqlx::query_as!(
+            LeasedTask,
+            r#"SELECT NULLIF(category, '') AS category, status AS "status: _", submission_key,
+                      submission_report
+               FROM tasks
+               WHERE submission_date = $1 AND status = ANY($2) AND lease_id = $3"#,
+            submission_date,
+            &[models::Status::Processing, models::Status::Done] as &[models::Status],
+            lease_id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(Error::LeaseMismatch(submission_date))
+    }
+
+    /// Check the lease and lock the task for the time of submission. Papers
+    /// are inserted in the returned transaction which is then passed to
+    /// [`DBConnection::finish_submission`]. Task which was already submitted
+    /// under this lease is returned too, so that the submission can be
+    /// replayed.
+    pub async fn begin_submission(
+        &self,
+        submission_date: chrono::NaiveDate,
+        lease_id: uuid::Uuid,
+    ) -> Result<(sqlx::Transaction<'static, sqlx::Postgres>, LeasedTask)> {
         let mut tx = self.pool.begin().await?;
 
-        self.insert_papers_full(submission.papers, &mut tx).await?;
+        let task = sqlx::query_as!(
+            LeasedTask,
+            r#"SELECT NULLIF(category, '') AS category, status AS "status: _", submission_key,
+                      submission_report
+               FROM tasks
+               WHERE submission_date = $1 AND status = ANY($2) AND lease_id = $3
+               FOR UPDATE"#,
+            submission_date,
+            &[models::Status::Processing, models::Status::Done] as &[models::Status],
+            lease_id
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::LeaseMismatch(submission_date))?;
+
+        Ok((tx, task))
+    }
+
+    pub async fn begin_import(&self) -> Result<sqlx::Transaction<'static, sqlx::Postgres>> {
+        Ok(self.pool.begin().await?)
+    }
+
+    /// Mark days covered by imported papers as done, including tasks for
+    /// single categories of these days, so that scrapers do not fetch them
+    /// again, and commit the import batch.
+    pub async fn finish_import(
+        &self,
+        mut tx: sqlx::Transaction<'_, sqlx::Postgres>,
+        submission_dates: &[chrono::NaiveDate],
+        imported_by: &str,
+    ) -> Result<()> {
+        log::trace!(
+            "DB: marking {} imported days as done",
+            submission_dates.len()
+        );
+
+        // NOTE: tasks being processed are left alone, their submission just
+        // duplicates imported papers
+        sqlx::query!(
+            "INSERT INTO tasks (submission_date, status, processing_end, submitted_by)
+             SELECT submission_date, $2, $3, $4
+             FROM UNNEST ($1::date[]) AS submission_date
+             ON CONFLICT (submission_date, category) DO UPDATE
+             SET status = EXCLUDED.status, processing_end = EXCLUDED.processing_end,
+                 lease_id = NULL, lease_expires_at = NULL,
+                 submitted_by = EXCLUDED.submitted_by, last_error = NULL, retry_after = NULL,
+                 submission_key = NULL, submission_report = NULL
+             WHERE tasks.status <> $5",
+            submission_dates,
+            models::Status::Done as models::Status,
+            chrono::Utc::now().naive_utc(),
+            imported_by,
+            models::Status::Processing as models::Status,
+        )
+        .execute(&mut *tx)
+        .await?;
 
         sqlx::query!(
             "UPDATE tasks
-             SET status = $1, processing_end = $2
-             WHERE submission_date = $3",
+             SET status = $1, processing_end = $2, lease_id = NULL, lease_expires_at = NULL,
+                 submitted_by = $3, last_error = NULL, retry_after = NULL,
+                 submission_key = NULL, submission_report = NULL
+             WHERE submission_date = ANY($4) AND category <> '' AND status <> $5",
             models::Status::Done as models::Status,
             chrono::Utc::now().naive_utc(),
-            submission.submission_date,
+            imported_by,
+            submission_dates,
+            models::Status::Processing as models::Status,
         )
         .execute(&mut *tx)
         .await?;
@@ -377,25 +1707,243 @@ impl DBConnection {
         Ok(())
     }
 
-    pub async fn revert_long_running_tasks(&self, threshold_seconds: u64) -> Result<()> {
-        let threshold = std::time::Duration::from_secs(threshold_seconds);
-        let threshold = sqlx::postgres::types::PgInterval::try_from(threshold).unwrap();
+    pub async fn finish_submission(
+        &self,
+        mut tx: sqlx::Transaction<'_, sqlx::Postgres>,
+        submission_date: chrono::NaiveDate,
+        lease_id: uuid::Uuid,
+        worker: &str,
+        submission_key: &str,
+        report: &models::IngestionReport,
+    ) -> Result<()> {
+        // NOTE: lease is kept, so that retried submission can be recognized
+        let previous = sqlx::query_scalar!(
+            r#"UPDATE tasks
+               SET status = $1, processing_end = $2, lease_expires_at = NULL,
+                   submitted_by = $3, last_error = NULL,
+                   submission_key = $6, submission_report = $7
+               FROM tasks AS previous
+               WHERE previous.submission_date = tasks.submission_date
+               AND previous.category = tasks.category
+               AND tasks.submission_date = $4 AND tasks.lease_id = $5
+               RETURNING previous.status AS "status: models::Status""#,
+            models::Status::Done as models::Status,
+            chrono::Utc::now().naive_utc(),
+            worker,
+            submission_date,
+            lease_id,
+            submission_key,
+            serde_json::to_string(report)?,
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.touch_worker(worker, &mut tx).await?;
+        // NOTE: forced resubmission is of a task already counted
+        if previous != models::Status::Done {
+            sqlx::query!(
+                "UPDATE workers SET completed = completed + 1
+                 WHERE name = $1",
+                worker
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Keep invalid papers of a rejected submission for review.
+    pub async fn insert_quarantine(
+        &self,
+        submission_date: chrono::NaiveDate,
+        category: Option<&str>,
+        worker: &str,
+        papers: &[models::InvalidPaper],
+    ) -> Result<()> {
+        log::trace!(
+            "DB: quarantining {} papers of task {:?}",
+            papers.len(),
+            submission_date
+        );
+
+        let mut lines = Vec::with_capacity(papers.len());
+        let mut arxiv_ids = Vec::with_capacity(papers.len());
+        let mut jsons = Vec::with_capacity(papers.len());
+        let mut errors = Vec::with_capacity(papers.len());
+        for paper in papers {
+            lines.push(paper.line as i32);
+            arxiv_ids.push(paper.arxiv_id.clone());
+            jsons.push(
+                paper
+                    .paper
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?,
+            );
+            // NOTE: arrays of different length can not be unnested, so every
+            // list of errors is passed as JSON
+            errors.push(serde_json::to_string(&paper.errors)?);
+        }
+
+        sqlx::query!(
+            r#"INSERT INTO quarantine
+               (submission_date, category, worker, line, arxiv_id, paper, errors)
+               SELECT $1, COALESCE($2, ''), $3, line, arxiv_id, paper::jsonb,
+                      ARRAY(SELECT jsonb_array_elements_text(errors::jsonb))
+               FROM UNNEST ($4::integer[], $5::varchar[], $6::text[], $7::text[])
+               AS input (line, arxiv_id, paper, errors)"#,
+            submission_date,
+            category,
+            worker,
+            &lines[..],
+            &arxiv_ids[..] as &[Option<String>],
+            &jsons[..] as &[Option<String>],
+            &errors[..],
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_quarantine(
+        &self,
+        submission_date: chrono::NaiveDate,
+    ) -> Result<Vec<models::QuarantinedPaper>> {
+        let rows = sqlx::query!(
+            r#"SELECT id, submission_date, NULLIF(category, '') AS category, worker, line,
+                      arxiv_id, paper::text AS paper, errors, created_at
+               FROM quarantine
+               WHERE submission_date = $1
+               ORDER BY id"#,
+            submission_date
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let paper = row.paper.as_deref().map(serde_json::from_str).transpose()?;
+                Ok(models::QuarantinedPaper {
+                    id: row.id,
+                    submission_date: row.submission_date,
+                    category: row.category,
+                    worker: row.worker,
+                    line: row.line,
+                    arxiv_id: row.arxiv_id,
+                    paper,
+                    errors: row.errors,
+                    created_at: row.created_at,
+                })
+            })
+            .collect()
+    }
+
+    /// Give the task back to the queue after scraper reported an error. Task is
+    /// retried after a backoff until it runs out of attempts.
+    pub async fn fail_task(
+        &self,
+        submission_date: chrono::NaiveDate,
+        failure: models::TaskFailure,
+        worker: &str,
+        retry: &RetryPolicy,
+    ) -> Result<models::Status> {
+        log::trace!(
+            "DB: task {:?} failed by {:?}: {}",
+            submission_date,
+            worker,
+            failure.error
+        );
+
+        let mut tx = self.pool.begin().await?;
+
+        let status = sqlx::query_scalar!(
+            r#"UPDATE tasks
+               SET status = CASE WHEN attempts >= $1 THEN $2::status ELSE $3::status END,
+                   retry_after = CASE WHEN attempts >= $1 THEN NULL
+                                 ELSE $4::timestamp + LEAST($5::float8 * power(2, LEAST(attempts - 1, 62)), $10) * interval '1 second' END,
+                   processing_start = NULL, lease_id = NULL, lease_expires_at = NULL,
+                   last_error = $6
+               WHERE submission_date = $7 AND status = $8 AND lease_id = $9
+               RETURNING status AS "status!: models::Status""#,
+            retry.max_attempts,
+            models::Status::Failed as models::Status,
+            models::Status::Idle as models::Status,
+            chrono::Utc::now().naive_utc(),
+            retry.backoff_seconds as f64,
+            failure.error,
+            submission_date,
+            models::Status::Processing as models::Status,
+            failure.lease_id,
+            MAX_RETRY_BACKOFF_SECONDS,
+        )
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(Error::LeaseMismatch(submission_date))?;
 
-        // TODO:: Use SELECT FOR UPDATE
+        self.touch_worker(worker, &mut tx).await?;
         sqlx::query!(
-            "UPDATE tasks
-             SET status = $1, processing_start = $2
-             WHERE status = $3
-             AND (current_timestamp - processing_start > $4 OR processing_start IS NULL)",
+            "UPDATE workers SET failed = failed + 1
+             WHERE name = $1",
+            worker
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(status)
+    }
+
+    /// Take back tasks with expired lease, returns number of reverted tasks.
+    pub async fn revert_expired_leases(&self, retry: &RetryPolicy) -> Result<usize> {
+        let mut tx = self.pool.begin().await?;
+
+        let now = chrono::Utc::now().naive_utc();
+        let workers = sqlx::query_scalar!(
+            r#"UPDATE tasks
+               SET status = CASE WHEN attempts >= $1 THEN $2::status ELSE $3::status END,
+                   retry_after = CASE WHEN attempts >= $1 THEN NULL
+                                 ELSE $4::timestamp + LEAST($5::float8 * power(2, LEAST(attempts - 1, 62)), $7) * interval '1 second' END,
+                   processing_start = NULL, lease_id = NULL, lease_expires_at = NULL,
+                   last_error = 'Lease expired'
+               WHERE status = $6
+               AND (lease_expires_at < $4 OR lease_expires_at IS NULL)
+               RETURNING worker"#,
+            retry.max_attempts,
+            models::Status::Failed as models::Status,
             models::Status::Idle as models::Status,
-            None as Option<chrono::NaiveDateTime>,
+            now,
+            retry.backoff_seconds as f64,
             models::Status::Processing as models::Status,
-            threshold
+            MAX_RETRY_BACKOFF_SECONDS,
         )
-        .execute(&self.pool)
+        .fetch_all(&mut *tx)
         .await?;
 
-        Ok(())
+        if !workers.is_empty() {
+            log::info!("DB: reclaimed {} tasks with expired lease", workers.len());
+            self.notify_tasks_available(&mut tx).await?;
+        }
+
+        let reverted = workers.len();
+        let workers = workers.into_iter().flatten().collect::<Vec<_>>();
+        sqlx::query!(
+            "UPDATE workers SET failed = failed + expired.count
+             FROM (SELECT name, COUNT(*) AS count FROM UNNEST ($1::varchar[]) AS name
+                   GROUP BY name) AS expired
+             WHERE workers.name = expired.name",
+            &workers[..]
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(reverted)
     }
 
     pub async fn get_db_size_mb(&self) -> Result<f64> {
@@ -407,4 +1955,595 @@ impl DBConnection {
         let size_mb = size_bytes as f64 / 1024. / 1024.;
         Ok(size_mb)
     }
+
+    pub async fn insert_api_key(
+        &self,
+        name: &str,
+        key_hash: &str,
+        scope: models::Scope,
+    ) -> Result<models::ApiKey> {
+        log::trace!("DB: inserting API key {name:?}");
+
+        sqlx::query_as!(
+            models::ApiKey,
+            r#"INSERT INTO api_keys (name, key_hash, scope)
+               VALUES ($1, $2, $3)
+               RETURNING id, name, scope AS "scope: _", created_at"#,
+            name,
+            key_hash,
+            scope as models::Scope,
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    pub async fn get_api_keys(&self) -> Result<Vec<models::ApiKey>> {
+        sqlx::query_as!(
+            models::ApiKey,
+            r#"SELECT id, name, scope AS "scope: _", created_at
+               FROM api_keys
+               ORDER BY id"#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Returns false if there is no key with such name.
+    pub async fn delete_api_key(&self, name: &str) -> Result<bool> {
+        log::trace!("DB: deleting API key {name:?}");
+
+        let deleted = sqlx::query!("DELETE FROM api_keys WHERE name = $1", name)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+
+        Ok(deleted > 0)
+    }
+
+    pub async fn get_api_key_scope(&self, key_hash: &str) -> Result<Option<models::Scope>> {
+        sqlx::query_scalar!(
+            r#"SELECT scope AS "scope: models::Scope" FROM api_keys
+               WHERE key_hash = $1"#,
+            key_hash
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    pub fn pool_stats(&self) -> PoolStats {
+        PoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle(),
+            max: self.pool.options().get_max_connections(),
+        }
+    }
+}
+
+/// Escape characters which have special meaning in LIKE patterns.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn lease_duration(lease_seconds: u64) -> chrono::Duration {
+    chrono::Duration::seconds(lease_seconds as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Many scrapers claim tasks at the same time, every day has to be given
+    /// out exactly once.
+    #[sqlx::test]
+    async fn concurrent_claims_do_not_overlap(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let days = 300;
+        let claimers = 30;
+
+        let first = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let dates = first.iter_days().take(days).collect::<Vec<_>>();
+        let new_tasks = dates
+            .iter()
+            .map(|&submission_date| models::NewTask {
+                submission_date,
+                category: None,
+            })
+            .collect();
+        db.insert_task(new_tasks, None).await.unwrap();
+
+        let claims = (0..claimers).map(|i| {
+            let db = &db;
+            async move {
+                let worker = format!("claimer-{i}");
+                let mut claimed = Vec::new();
+                loop {
+                    let tasks = db
+                        .get_next_tasks(60, &worker, DispatchOrder::OldestFirst, 1 + i % 4)
+                        .await
+                        .unwrap();
+                    if tasks.is_empty() {
+                        break;
+                    }
+                    claimed.extend(tasks.into_iter().map(|task| task.submission_date));
+                }
+                claimed
+            }
+        });
+        let claimed = futures::future::join_all(claims)
+            .await
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let unique = claimed.iter().collect::<HashSet<_>>();
+        assert_eq!(
+            unique.len(),
+            claimed.len(),
+            "some days were given out twice"
+        );
+        assert_eq!(claimed.len(), days);
+    }
+
+    fn paper(version: i32, title: &str) -> NewPaperFull {
+        NewPaperFull {
+            arxiv_id: "2401.00001".to_string(),
+            title: title.to_string(),
+            description: "Description".to_string(),
+            submission_date: chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap(),
+            body: "Body".to_string(),
+            version,
+            authors: vec![NewAuthor {
+                name: "Author".to_string(),
+            }],
+            subjects: vec![NewSubject {
+                name: "cs.LG".to_string(),
+            }],
+        }
+    }
+
+    async fn submit(db: &DBConnection, paper: NewPaperFull) -> models::IngestionReport {
+        let mut report = models::IngestionReport::default();
+        let mut tx = db.begin_import().await.unwrap();
+        db.insert_papers_full(vec![paper], &mut report, &mut tx)
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        report
+    }
+
+    /// A late resubmission of an older version must not replace the newer one.
+    #[sqlx::test]
+    async fn older_version_does_not_replace_newer(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+
+        submit(&db, paper(2, "Second")).await;
+        let report = submit(&db, paper(1, "First")).await;
+        assert_eq!(report.updated, 0);
+        assert_eq!(report.skipped, ["2401.00001"]);
+
+        let stored = db
+            .get_paper_by_arxiv_id("2401.00001")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.version, 2);
+        assert_eq!(stored.title, "Second");
+        assert_eq!(db.get_paper_versions("2401.00001").await.unwrap().len(), 1);
+
+        let report = submit(&db, paper(3, "Third")).await;
+        assert_eq!(report.updated, 1);
+        let stored = db
+            .get_paper_by_arxiv_id("2401.00001")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.version, 3);
+        assert_eq!(stored.title, "Third");
+    }
+
+    /// Changed content needs a new version, which replaces authors and
+    /// subjects too.
+    #[sqlx::test]
+    async fn new_version_replaces_links(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+
+        submit(&db, paper(1, "First")).await;
+        let report = submit(&db, paper(1, "Changed")).await;
+        assert_eq!(report.unchanged_version_conflicts, ["2401.00001"]);
+        assert!(report.skipped.is_empty());
+        assert_eq!(report.updated, 0);
+
+        let report = submit(
+            &db,
+            NewPaperFull {
+                authors: vec![NewAuthor {
+                    name: "New author".to_string(),
+                }],
+                subjects: vec![NewSubject {
+                    name: "hep-th".to_string(),
+                }],
+                ..paper(2, "Second")
+            },
+        )
+        .await;
+        assert_eq!(report.updated, 1);
+
+        let stored = db
+            .get_paper_by_arxiv_id("2401.00001")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.title, "Second");
+        let authors = db.get_paper_authors(stored.id).await.unwrap();
+        assert_eq!(
+            authors.into_iter().map(|a| a.name).collect::<Vec<_>>(),
+            ["New author"]
+        );
+        let subjects = db.get_paper_subjects(stored.id).await.unwrap();
+        assert_eq!(
+            subjects.into_iter().map(|s| s.name).collect::<Vec<_>>(),
+            ["hep-th"]
+        );
+    }
+
+    /// Import must not take away a day which a scraper is working on.
+    #[sqlx::test]
+    async fn import_keeps_processing_task(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        db.insert_task(
+            vec![models::NewTask {
+                submission_date: date,
+                category: None,
+            }],
+            None,
+        )
+        .await
+        .unwrap();
+        let claimed = db
+            .get_next_tasks(60, "scraper", DispatchOrder::OldestFirst, 1)
+            .await
+            .unwrap()
+            .remove(0);
+
+        let tx = db.begin_import().await.unwrap();
+        db.finish_import(tx, &[date], "import").await.unwrap();
+
+        let task = db.get_task(date, None).await.unwrap().unwrap();
+        assert_eq!(task.status, models::Status::Processing);
+        assert_eq!(task.lease_id, claimed.lease_id);
+    }
+
+    /// Days scraped by categories report status of their category tasks.
+    #[sqlx::test]
+    async fn coverage_of_split_days(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let first = chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let second = first.succ_opt().unwrap();
+        let tasks = [
+            (first, None),
+            (second, Some("cs.LG")),
+            (second, Some("hep-th")),
+        ]
+        .into_iter()
+        .map(|(submission_date, category)| models::NewTask {
+            submission_date,
+            category: category.map(str::to_string),
+        })
+        .collect();
+        db.insert_task(tasks, None).await.unwrap();
+        db.get_next_tasks(60, "scraper", DispatchOrder::OldestFirst, 2)
+            .await
+            .unwrap();
+
+        let days = db
+            .get_coverage(first, second.succ_opt().unwrap())
+            .await
+            .unwrap();
+        let statuses = days
+            .iter()
+            .map(|day| (day.status, day.split))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            statuses,
+            [
+                (Some(models::Status::Processing), false),
+                (Some(models::Status::Processing), true),
+                (None, false),
+            ]
+        );
+    }
+
+    /// Forced resubmission does not count as one more completed task.
+    #[sqlx::test]
+    async fn resubmission_is_counted_once(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        db.insert_task(
+            vec![models::NewTask {
+                submission_date: date,
+                category: None,
+            }],
+            None,
+        )
+        .await
+        .unwrap();
+        let lease_id = db
+            .get_next_tasks(60, "scraper", DispatchOrder::OldestFirst, 1)
+            .await
+            .unwrap()
+            .remove(0)
+            .lease_id
+            .unwrap();
+
+        let report = models::IngestionReport::default();
+        for key in ["first", "second"] {
+            let (tx, _) = db.begin_submission(date, lease_id).await.unwrap();
+            db.finish_submission(tx, date, lease_id, "scraper", key, &report)
+                .await
+                .unwrap();
+        }
+
+        let workers = db.get_workers().await.unwrap();
+        assert_eq!(workers[0].completed, 1);
+    }
+
+    /// Insert papers one query at a time, the way it was done before
+    /// [`DBConnection::insert_papers_full`], to compare against it.
+    async fn insert_papers_one_by_one(
+        db: &DBConnection,
+        papers: Vec<NewPaperFull>,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) {
+        for paper in papers {
+            if db.paper_exists(&paper.arxiv_id).await.unwrap() {
+                continue;
+            }
+
+            let paper_id = sqlx::query_scalar!(
+                "INSERT INTO papers (arxiv_id, title, description, submission_date, body, version)
+                 VALUES ($1, $2, $3, $4, $5, $6)
+                 RETURNING id",
+                paper.arxiv_id,
+                paper.title,
+                paper.description,
+                paper.submission_date,
+                paper.body,
+                paper.version,
+            )
+            .fetch_one(&mut **tx)
+            .await
+            .unwrap();
+
+            for author in paper.authors {
+                sqlx::query!(
+                    "INSERT INTO authors (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+                    author.name
+                )
+                .execute(&mut **tx)
+                .await
+                .unwrap();
+                let author_id =
+                    sqlx::query_scalar!("SELECT id FROM authors WHERE name = $1", author.name)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .unwrap();
+                sqlx::query!(
+                    "INSERT INTO paper_author (paper_id, author_id) VALUES ($1, $2)
+                     ON CONFLICT ON CONSTRAINT paper_author_pkey DO NOTHING",
+                    paper_id,
+                    author_id
+                )
+                .execute(&mut **tx)
+                .await
+                .unwrap();
+            }
+
+            for subject in paper.subjects {
+                sqlx::query!(
+                    "INSERT INTO subjects (name) VALUES ($1) ON CONFLICT (name) DO NOTHING",
+                    subject.name
+                )
+                .execute(&mut **tx)
+                .await
+                .unwrap();
+                let subject_id =
+                    sqlx::query_scalar!("SELECT id FROM subjects WHERE name = $1", subject.name)
+                        .fetch_one(&mut **tx)
+                        .await
+                        .unwrap();
+                sqlx::query!(
+                    "INSERT INTO paper_subject (paper_id, subject_id) VALUES ($1, $2)
+                     ON CONFLICT ON CONSTRAINT paper_subject_pkey DO NOTHING",
+                    paper_id,
+                    subject_id
+                )
+                .execute(&mut **tx)
+                .await
+                .unwrap();
+            }
+        }
+    }
+
+    fn generate_papers(prefix: &str, count: usize) -> Vec<NewPaperFull> {
+        (0..count)
+            .map(|i| NewPaperFull {
+                arxiv_id: format!("{prefix}.{i:05}"),
+                title: format!("Paper {i}"),
+                description: "Benchmark paper".to_string(),
+                submission_date: chrono::NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+                body: "Lorem ipsum dolor sit amet".repeat(100),
+                version: 1,
+                authors: (0..5)
+                    .map(|j| NewAuthor {
+                        name: format!("{prefix} Author {}", (i + j) % 200),
+                    })
+                    .collect(),
+                subjects: (0..3)
+                    .map(|j| NewSubject {
+                        name: format!("{prefix} subject {}", (i + j) % 20),
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Scheduled enqueueing never adds a whole day task to a day which is
+    /// scraped by categories.
+    #[sqlx::test]
+    async fn requeue_leaves_split_days_alone(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let split = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let whole = split.succ_opt().unwrap();
+        let missing = whole.succ_opt().unwrap();
+        let tasks = [(split, Some("cs.LG".to_string())), (whole, None)]
+            .into_iter()
+            .map(|(submission_date, category)| models::NewTask {
+                submission_date,
+                category,
+            })
+            .collect();
+        db.insert_task(tasks, None).await.unwrap();
+        let report = models::IngestionReport::default();
+        for task in db
+            .get_next_tasks(60, "scraper", DispatchOrder::OldestFirst, 2)
+            .await
+            .unwrap()
+        {
+            let lease_id = task.lease_id.unwrap();
+            let (tx, _) = db
+                .begin_submission(task.submission_date, lease_id)
+                .await
+                .unwrap();
+            db.finish_submission(
+                tx,
+                task.submission_date,
+                lease_id,
+                "scraper",
+                "key",
+                &report,
+            )
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(db.enqueue_days(&[split, whole, missing]).await.unwrap(), 1);
+        assert_eq!(db.requeue_tasks(&[split, whole]).await.unwrap(), 1);
+
+        let (total, tasks) = db
+            .list_tasks(None, None, &DateRange::default(), 10, 0)
+            .await
+            .unwrap();
+        let tasks = tasks
+            .into_iter()
+            .map(|task| (task.submission_date, task.category, task.status))
+            .collect::<Vec<_>>();
+        assert_eq!(total, 3);
+        assert_eq!(
+            tasks,
+            [
+                (split, Some("cs.LG".to_string()), models::Status::Done),
+                (whole, None, models::Status::Idle),
+                (missing, None, models::Status::Idle),
+            ]
+        );
+    }
+
+    /// Papers with any number of errors, parsed or not, are quarantined.
+    #[sqlx::test]
+    async fn quarantine_round_trip(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let date = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+        let invalid = [
+            models::InvalidPaper {
+                line: 1,
+                arxiv_id: None,
+                errors: vec!["expected value".to_string()],
+                paper: None,
+            },
+            models::InvalidPaper {
+                line: 2,
+                arxiv_id: Some("2401.00001".to_string()),
+                errors: vec!["Empty title".to_string(), "Empty body".to_string()],
+                paper: Some(paper(1, "")),
+            },
+        ];
+        db.insert_quarantine(date, Some("cs.LG"), "scraper", &invalid)
+            .await
+            .unwrap();
+
+        let quarantined = db.get_quarantine(date).await.unwrap();
+        assert_eq!(quarantined.len(), 2);
+        assert_eq!(quarantined[0].errors, invalid[0].errors);
+        assert!(quarantined[0].paper.is_none());
+        assert_eq!(quarantined[1].errors, invalid[1].errors);
+        assert_eq!(quarantined[1].arxiv_id.as_deref(), Some("2401.00001"));
+        assert_eq!(quarantined[1].category.as_deref(), Some("cs.LG"));
+        assert_eq!(quarantined[1].paper.as_ref().unwrap()["version"], 1);
+    }
+
+    /// Submissions sharing papers, e.g. cross-listed ones of category tasks,
+    /// must not deadlock whatever order the papers come in.
+    #[sqlx::test]
+    async fn overlapping_submissions_do_not_deadlock(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let count = 1000;
+
+        let submissions = [false, true].map(|reversed| {
+            let db = &db;
+            let mut papers = generate_papers("3333", count);
+            if reversed {
+                papers.reverse();
+            }
+            async move {
+                let mut report = models::IngestionReport::default();
+                let mut tx = db.pool.begin().await?;
+                db.insert_papers_full(papers, &mut report, &mut tx).await?;
+                tx.commit().await?;
+                Ok::<_, Error>(())
+            }
+        });
+        for result in futures::future::join_all(submissions).await {
+            result.unwrap();
+        }
+
+        assert_eq!(db.count_papers().await.unwrap(), count as i64);
+    }
+
+    /// Run with `cargo test bench -- --ignored --nocapture`.
+    #[sqlx::test]
+    #[ignore = "benchmark"]
+    async fn bench_insert_papers_full(pool: sqlx::PgPool) {
+        let db = DBConnection { pool };
+        let count = 1000;
+
+        let papers = generate_papers("1111", count);
+        let mut tx = db.pool.begin().await.unwrap();
+        let started = std::time::Instant::now();
+        insert_papers_one_by_one(&db, papers, &mut tx).await;
+        let one_by_one = started.elapsed();
+        tx.commit().await.unwrap();
+
+        let papers = generate_papers("2222", count);
+        let mut report = models::IngestionReport::default();
+        let mut tx = db.pool.begin().await.unwrap();
+        let started = std::time::Instant::now();
+        db.insert_papers_full(papers, &mut report, &mut tx)
+            .await
+            .unwrap();
+        let batched = started.elapsed();
+        tx.commit().await.unwrap();
+
+        println!(
+            "{count} papers: one by one {one_by_one:?}, batched {batched:?} ({:.1}x faster)",
+            one_by_one.as_secs_f64() / batched.as_secs_f64()
+        );
+        assert_eq!(report.inserted, count);
+        assert_eq!(db.count_papers().await.unwrap(), 2 * count as i64);
+    }
 }
@@ -1,31 +1,208 @@
-use actix_multipart::Multipart;
+use actix_multipart::{Field, Multipart, MultipartError};
 use futures::{StreamExt, TryStreamExt};
-use std::collections::HashMap;
-use std::sync::Arc;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::Mutex;
-
-use lazy_static::lazy_static;
-
-lazy_static! {
-    pub static ref UPLOAD_STORAGE: Arc<Mutex<HashMap<String, Vec<u8>>>> =
-        Arc::new(Mutex::new(HashMap::new()));
-}
-
-pub async fn save_file(mut payload: Multipart, key: String) -> anyhow::Result<()> {
-    while let Ok(Some(mut field)) = payload.try_next().await {
-        let mut storage = UPLOAD_STORAGE.lock().await;
-        storage.insert(key.clone(), Vec::new());
-
-        while let Some(chunk) = field.next().await {
-            let data = chunk.unwrap();
-            storage
-                .get_mut(&key)
-                .unwrap() // NOTE(mchernigin): key is inserted above
-                .write_all(&data)
-                .await?;
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("upload error: {0}")]
+    Multipart(#[from] MultipartError),
+    #[error("read error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("upload is larger than {0} bytes")]
+    TooLarge(usize),
+}
+impl actix_web::error::ResponseError for Error {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Error::Multipart(_) => actix_web::http::StatusCode::BAD_REQUEST,
+            Error::Io(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            Error::TooLarge(_) => actix_web::http::StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Record parsed from a single line, error is kept so that one malformed line
+/// does not fail the whole upload.
+pub struct Record<T> {
+    pub line: usize,
+    pub value: serde_json::Result<T>,
+}
+
+enum Source {
+    Multipart {
+        payload: Multipart,
+        field: Option<Box<Field>>,
+    },
+    Reader(Box<dyn AsyncRead + Unpin>),
+}
+
+/// Reads newline delimited JSON from the first field of multipart upload or
+/// from a file without keeping more than one unfinished line in memory.
+pub struct NdjsonUpload {
+    source: Source,
+    buf: Vec<u8>,
+    /// Part of `buf` already known to have no newlines
+    scanned: usize,
+    line: usize,
+    received: usize,
+    /// Hash of everything read so far
+    hasher: Sha256,
+    max_size: usize,
+    finished: bool,
+}
+
+impl NdjsonUpload {
+    pub fn new(payload: Multipart, max_size: usize) -> Self {
+        Self::with_source(
+            Source::Multipart {
+                payload,
+                field: None,
+            },
+            max_size,
+        )
+    }
+
+    pub fn from_reader(reader: impl AsyncRead + Unpin + 'static) -> Self {
+        Self::with_source(Source::Reader(Box::new(reader)), usize::MAX)
+    }
+
+    fn with_source(source: Source, max_size: usize) -> Self {
+        NdjsonUpload {
+            source,
+            buf: Vec::new(),
+            scanned: 0,
+            line: 0,
+            received: 0,
+            hasher: Sha256::new(),
+            max_size,
+            finished: false,
         }
     }
 
-    Ok(())
+    /// Save the rest of the upload to a temporary file without parsing it, so
+    /// that the upload is received before anything is done with it and can
+    /// be read several times.
+    pub async fn spool(mut self) -> Result<SpooledUpload> {
+        let mut file = tokio::fs::File::from_std(tempfile::tempfile()?);
+        while !self.finished {
+            self.read_chunk().await?;
+            file.write_all(&self.buf).await?;
+            self.buf.clear();
+        }
+        file.flush().await?;
+
+        Ok(SpooledUpload {
+            file,
+            size: self.received,
+            digest: format!("{:x}", self.hasher.finalize()),
+        })
+    }
+
+    /// Parse up to `size` next records. Empty batch means upload is over.
+    pub async fn next_batch<T: DeserializeOwned>(&mut self, size: usize) -> Result<Vec<Record<T>>> {
+        let mut batch = Vec::new();
+
+        while batch.len() < size {
+            if let Some(pos) = self.buf[self.scanned..].iter().position(|b| *b == b'\n') {
+                let line = self.buf.drain(..=self.scanned + pos).collect::<Vec<_>>();
+                self.scanned = 0;
+                if let Some(record) = self.parse_line(&line) {
+                    batch.push(record);
+                }
+            } else if self.finished {
+                if !self.buf.is_empty() {
+                    let line = std::mem::take(&mut self.buf);
+                    self.scanned = 0;
+                    if let Some(record) = self.parse_line(&line) {
+                        batch.push(record);
+                    }
+                }
+                break;
+            } else {
+                self.scanned = self.buf.len();
+                self.read_chunk().await?;
+            }
+        }
+
+        Ok(batch)
+    }
+
+    async fn read_chunk(&mut self) -> Result<()> {
+        let chunk = match &mut self.source {
+            Source::Multipart { payload, field } => {
+                let field = match field {
+                    Some(field) => field,
+                    None => match payload.try_next().await? {
+                        Some(new_field) => field.insert(Box::new(new_field)),
+                        None => {
+                            self.finished = true;
+                            return Ok(());
+                        }
+                    },
+                };
+
+                // NOTE: only the first field is read, the rest are ignored
+                match field.next().await {
+                    Some(chunk) => chunk?.to_vec(),
+                    None => Vec::new(),
+                }
+            }
+            Source::Reader(reader) => {
+                let mut chunk = vec![0; READ_CHUNK_SIZE];
+                let read = reader.read(&mut chunk).await?;
+                chunk.truncate(read);
+                chunk
+            }
+        };
+
+        if chunk.is_empty() {
+            self.finished = true;
+            return Ok(());
+        }
+
+        self.received += chunk.len();
+        if self.received > self.max_size {
+            return Err(Error::TooLarge(self.max_size));
+        }
+        self.hasher.update(&chunk);
+        self.buf.extend_from_slice(&chunk);
+
+        Ok(())
+    }
+
+    fn parse_line<T: DeserializeOwned>(&mut self, line: &[u8]) -> Option<Record<T>> {
+        self.line += 1;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return None;
+        }
+
+        Some(Record {
+            line: self.line,
+            value: serde_json::from_slice(line),
+        })
+    }
+}
+
+/// Upload received in full, see [`NdjsonUpload::spool`].
+pub struct SpooledUpload {
+    file: tokio::fs::File,
+    /// Size of the upload in bytes
+    pub size: usize,
+    /// SHA-256 of the upload, hex encoded
+    pub digest: String,
+}
+
+impl SpooledUpload {
+    /// Read the upload from the beginning.
+    pub async fn reader(&self) -> Result<NdjsonUpload> {
+        let mut file = self.file.try_clone().await?;
+        file.rewind().await?;
+        Ok(NdjsonUpload::from_reader(file))
+    }
 }
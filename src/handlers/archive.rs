@@ -1,16 +1,113 @@
-use crate::{db, models::ArchiveStats};
+use crate::{
+    auth::RequireScope,
+    config::Config,
+    db,
+    file_upload::NdjsonUpload,
+    import,
+    models::{ArchiveStats, PaperProjection, PapersCursorPage, PapersPage, SearchResults},
+};
+use actix_multipart::Multipart;
 use actix_web::{
-    get,
-    web::{Data, Path},
+    get, post,
+    web::{Bytes, Data, Path, Query},
     HttpResponse, Result,
 };
+use futures::TryStreamExt;
+
+const DEFAULT_PAGE_LIMIT: i64 = 20;
+const MAX_PAGE_LIMIT: i64 = 100;
+
+/// Clamp user provided pagination to sane values.
+fn page(limit: Option<i64>, offset: Option<i64>) -> (i64, i64) {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_LIMIT).clamp(1, MAX_PAGE_LIMIT);
+    let offset = offset.unwrap_or(0).max(0);
+    (limit, offset)
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct SearchQuery {
+    /// Search query, supports `"quoted phrases"`, `or` and `-excluded` words
+    q: String,
+    /// Number of papers to return (default 20, max 100)
+    limit: Option<i64>,
+    /// Number of papers to skip
+    offset: Option<i64>,
+}
+
+const PAPER_FIELDS: [&str; 7] = [
+    "id",
+    "arxiv_id",
+    "title",
+    "description",
+    "submission_date",
+    "body",
+    "version",
+];
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct ListPapersQuery {
+    /// Earliest submission date, inclusive
+    from: Option<chrono::NaiveDate>,
+    /// Latest submission date, inclusive
+    to: Option<chrono::NaiveDate>,
+    /// Only papers in this subject
+    subject: Option<String>,
+    /// Only papers of author with this id
+    author: Option<i32>,
+    /// `next_cursor` from the previous page
+    cursor: Option<String>,
+    /// Number of papers to return (default 20, max 100)
+    limit: Option<i64>,
+    /// Comma separated paper fields to return, all except `body` by default
+    fields: Option<String>,
+}
+
+const EXPORT_BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct ExportQuery {
+    /// Earliest submission date, inclusive
+    from: Option<chrono::NaiveDate>,
+    /// Latest submission date, inclusive
+    to: Option<chrono::NaiveDate>,
+    /// Only papers in this subject
+    subject: Option<String>,
+}
+
+/// Position in `(submission_date, id)` order, formatted as `{date}_{id}`.
+fn parse_cursor(cursor: &str) -> Option<(chrono::NaiveDate, i32)> {
+    let (date, id) = cursor.split_once('_')?;
+    Some((date.parse().ok()?, id.parse().ok()?))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct AuthorsQuery {
+    /// Case insensitive beginning of author name
+    prefix: Option<String>,
+    /// Number of authors to return (default 20, max 100)
+    limit: Option<i64>,
+    /// Number of authors to skip
+    offset: Option<i64>,
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct PapersQuery {
+    /// Earliest submission date, inclusive
+    from: Option<chrono::NaiveDate>,
+    /// Latest submission date, inclusive
+    to: Option<chrono::NaiveDate>,
+    /// Number of papers to return (default 20, max 100)
+    limit: Option<i64>,
+    /// Number of papers to skip
+    offset: Option<i64>,
+}
 
 #[utoipa::path(
     responses(
         (status = 200, description = "Get stats about archive", body = ArchiveStats)
     )
 )]
-#[get("/archive/stats")]
+#[get("/archive/stats", wrap = "RequireScope::read()")]
 pub async fn get_stats(db: Data<db::DBConnection>) -> Result<HttpResponse> {
     let stats = ArchiveStats {
         paper_count: db.count_papers().await?,
@@ -31,7 +128,7 @@ pub async fn get_stats(db: Data<db::DBConnection>) -> Result<HttpResponse> {
         ("day",)
     ),
 )]
-#[get("/archive/{year}/{month}/{day}")]
+#[get("/archive/{year}/{month}/{day}", wrap = "RequireScope::read()")]
 pub async fn get_papers_from_day(
     db: Data<db::DBConnection>,
     date: Path<(i32, u32, u32)>,
@@ -45,3 +142,376 @@ pub async fn get_papers_from_day(
 
     Ok(HttpResponse::Ok().json(papers))
 }
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Search papers by title, description and body", body = SearchResults),
+        (status = 400, description = "Invalid search query")
+    ),
+    params(SearchQuery),
+)]
+#[get("/archive/search", wrap = "RequireScope::read()")]
+pub async fn search_papers(
+    db: Data<db::DBConnection>,
+    query: Query<SearchQuery>,
+) -> Result<HttpResponse> {
+    let SearchQuery { q, limit, offset } = query.into_inner();
+    if q.trim().is_empty() {
+        return Ok(HttpResponse::BadRequest().body("Empty search query"));
+    }
+    let (limit, offset) = page(limit, offset);
+
+    let (total, papers) = db.search_papers(&q, limit, offset).await?;
+
+    Ok(HttpResponse::Ok().json(SearchResults {
+        total,
+        offset,
+        limit,
+        papers,
+    }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get paper with its authors and subjects", body = PaperFull),
+        (status = 404, description = "Paper not found")
+    ),
+    params(
+        ("id",),
+    ),
+)]
+#[get("/archive/papers/{id}", wrap = "RequireScope::read()")]
+pub async fn get_paper(db: Data<db::DBConnection>, id: Path<i32>) -> Result<HttpResponse> {
+    let Some(paper) = db.get_paper(id.into_inner()).await? else {
+        return Ok(HttpResponse::NotFound().body("Paper not found"));
+    };
+
+    Ok(HttpResponse::Ok().json(db.get_paper_full(paper).await?))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get paper with its authors and subjects", body = PaperFull),
+        (status = 404, description = "Paper not found")
+    ),
+    params(
+        ("arxiv_id",),
+    ),
+)]
+#[get(
+    "/archive/papers/by-arxiv/{arxiv_id:.+}",
+    wrap = "RequireScope::read()"
+)]
+pub async fn get_paper_by_arxiv_id(
+    db: Data<db::DBConnection>,
+    arxiv_id: Path<String>,
+) -> Result<HttpResponse> {
+    let Some(paper) = db.get_paper_by_arxiv_id(&arxiv_id.into_inner()).await? else {
+        return Ok(HttpResponse::NotFound().body("Paper not found"));
+    };
+
+    Ok(HttpResponse::Ok().json(db.get_paper_full(paper).await?))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get all known versions of the paper", body = [PaperVersionInfo]),
+        (status = 404, description = "Paper not found")
+    ),
+    params(
+        ("arxiv_id",),
+    ),
+)]
+#[get(
+    "/archive/papers/{arxiv_id:.+}/versions",
+    wrap = "RequireScope::read()"
+)]
+pub async fn get_paper_versions(
+    db: Data<db::DBConnection>,
+    path: Path<String>,
+) -> Result<HttpResponse> {
+    let versions = db.get_paper_versions(&path.into_inner()).await?;
+    if versions.is_empty() {
+        return Ok(HttpResponse::NotFound().body("Paper not found"));
+    }
+
+    Ok(HttpResponse::Ok().json(versions))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get specific version of the paper", body = PaperVersion),
+        (status = 404, description = "Paper or version not found")
+    ),
+    params(
+        ("arxiv_id",),
+        ("version",),
+    ),
+)]
+#[get(
+    "/archive/papers/{arxiv_id:.+}/versions/{version}",
+    wrap = "RequireScope::read()"
+)]
+pub async fn get_paper_version(
+    db: Data<db::DBConnection>,
+    path: Path<(String, i32)>,
+) -> Result<HttpResponse> {
+    let (arxiv_id, version) = path.into_inner();
+
+    match db.get_paper_version(&arxiv_id, version).await? {
+        Some(paper) => Ok(HttpResponse::Ok().json(paper)),
+        None => Ok(HttpResponse::NotFound().body("Paper version not found")),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get authors ordered by name", body = [AuthorStats])
+    ),
+    params(AuthorsQuery),
+)]
+#[get("/archive/authors", wrap = "RequireScope::read()")]
+pub async fn get_authors(
+    db: Data<db::DBConnection>,
+    query: Query<AuthorsQuery>,
+) -> Result<HttpResponse> {
+    let AuthorsQuery {
+        prefix,
+        limit,
+        offset,
+    } = query.into_inner();
+    let (limit, offset) = page(limit, offset);
+
+    let authors = db
+        .get_authors(prefix.as_deref().unwrap_or_default(), limit, offset)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(authors))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get papers of the author, newest first", body = PapersPage),
+        (status = 404, description = "Author not found")
+    ),
+    params(
+        ("id",),
+        PapersQuery,
+    ),
+)]
+#[get("/archive/authors/{id}/papers", wrap = "RequireScope::read()")]
+pub async fn get_author_papers(
+    db: Data<db::DBConnection>,
+    id: Path<i32>,
+    query: Query<PapersQuery>,
+) -> Result<HttpResponse> {
+    let id = id.into_inner();
+    let PapersQuery {
+        from,
+        to,
+        limit,
+        offset,
+    } = query.into_inner();
+    let (limit, offset) = page(limit, offset);
+
+    if !db.author_exists(id).await? {
+        return Ok(HttpResponse::NotFound().body("Author not found"));
+    }
+    let (total, papers) = db
+        .get_author_papers(id, db::DateRange { from, to }, limit, offset)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(PapersPage {
+        total,
+        offset,
+        limit,
+        papers,
+    }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get all subjects with number of papers in them", body = [SubjectStats])
+    )
+)]
+#[get("/archive/subjects", wrap = "RequireScope::read()")]
+pub async fn get_subjects(db: Data<db::DBConnection>) -> Result<HttpResponse> {
+    let subjects = db.get_subjects().await?;
+
+    Ok(HttpResponse::Ok().json(subjects))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get papers in the subject, newest first", body = PapersPage),
+        (status = 404, description = "Subject not found")
+    ),
+    params(
+        ("name",),
+        PapersQuery,
+    ),
+)]
+#[get("/archive/subjects/{name}/papers", wrap = "RequireScope::read()")]
+pub async fn get_subject_papers(
+    db: Data<db::DBConnection>,
+    name: Path<String>,
+    query: Query<PapersQuery>,
+) -> Result<HttpResponse> {
+    let name = name.into_inner();
+    let PapersQuery {
+        from,
+        to,
+        limit,
+        offset,
+    } = query.into_inner();
+    let (limit, offset) = page(limit, offset);
+
+    if !db.subject_exists(&name).await? {
+        return Ok(HttpResponse::NotFound().body("Subject not found"));
+    }
+    let (total, papers) = db
+        .get_subject_papers(&name, db::DateRange { from, to }, limit, offset)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(PapersPage {
+        total,
+        offset,
+        limit,
+        papers,
+    }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "List papers ordered by submission date", body = PapersCursorPage),
+        (status = 400, description = "Invalid cursor or unknown field")
+    ),
+    params(ListPapersQuery),
+)]
+#[get("/archive/papers", wrap = "RequireScope::read()")]
+pub async fn list_papers(
+    db: Data<db::DBConnection>,
+    query: Query<ListPapersQuery>,
+) -> Result<HttpResponse> {
+    let ListPapersQuery {
+        from,
+        to,
+        subject,
+        author,
+        cursor,
+        limit,
+        fields,
+    } = query.into_inner();
+    let (limit, _) = page(limit, None);
+
+    let after = match cursor.as_deref().map(parse_cursor) {
+        Some(None) => return Ok(HttpResponse::BadRequest().body("Invalid cursor")),
+        Some(after) => after,
+        None => None,
+    };
+
+    let fields = match &fields {
+        Some(fields) => fields.split(',').map(str::trim).collect::<Vec<_>>(),
+        None => PAPER_FIELDS.into_iter().filter(|f| *f != "body").collect(),
+    };
+    if let Some(unknown) = fields.iter().find(|f| !PAPER_FIELDS.contains(f)) {
+        return Ok(HttpResponse::BadRequest().body(format!("Unknown field {unknown:?}")));
+    }
+    let has = |field| fields.contains(&field);
+
+    let filter = db::PaperFilter {
+        range: db::DateRange { from, to },
+        subject,
+        author_id: author,
+    };
+    let papers = db
+        .list_papers(&filter, after, has("description"), has("body"), limit)
+        .await?;
+
+    let next_cursor = match papers.last() {
+        Some(PaperProjection {
+            submission_date: Some(date),
+            id: Some(id),
+            ..
+        }) if papers.len() as i64 == limit => Some(format!("{date}_{id}")),
+        _ => None,
+    };
+
+    let papers = papers
+        .into_iter()
+        .map(|paper| PaperProjection {
+            id: paper.id.filter(|_| has("id")),
+            arxiv_id: paper.arxiv_id.filter(|_| has("arxiv_id")),
+            title: paper.title.filter(|_| has("title")),
+            description: paper.description,
+            submission_date: paper.submission_date.filter(|_| has("submission_date")),
+            body: paper.body,
+            version: paper.version.filter(|_| has("version")),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(PapersCursorPage {
+        papers,
+        next_cursor,
+    }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Stream papers with their authors and subjects as NDJSON of \
+                                      NewPaperFull ordered by submission date. Compressed with gzip \
+                                      or zstd if requested in Accept-Encoding", content_type = "application/x-ndjson")
+    ),
+    params(ExportQuery),
+)]
+#[get("/archive/export", wrap = "RequireScope::read()")]
+pub async fn export_papers(
+    db: Data<db::DBConnection>,
+    query: Query<ExportQuery>,
+) -> Result<HttpResponse> {
+    let ExportQuery { from, to, subject } = query.into_inner();
+    let filter = db::PaperFilter {
+        range: db::DateRange { from, to },
+        subject,
+        author_id: None,
+    };
+
+    let papers = db.export_papers(&filter, EXPORT_BATCH_SIZE).await?;
+    let lines = papers.map_ok(|papers| {
+        let mut buf = Vec::new();
+        for paper in papers {
+            // NOTE: serializing plain data structure into Vec<u8> can not fail
+            serde_json::to_writer(&mut buf, &paper).unwrap();
+            buf.push(b'\n');
+        }
+        Bytes::from(buf)
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(lines))
+}
+
+#[utoipa::path(
+    request_body(
+        content = String,
+        content_type = "multipart/form-data",
+        description = "File with one NewPaperFull JSON object per line, e.g. made by /archive/export"
+    ),
+    responses(
+        (status = 200, description = "Papers imported and days they cover marked as done", body = IngestionReport),
+        (status = 400, description = "Invalid upload"),
+        (status = 413, description = "Import is too large")
+    ),
+)]
+#[post("/archive/import", wrap = "RequireScope::admin()")]
+pub async fn import_papers(
+    db: Data<db::DBConnection>,
+    config: Data<Config>,
+    payload: Multipart,
+) -> Result<HttpResponse> {
+    let mut upload = NdjsonUpload::new(payload, config.max_import_size_mb * 1024 * 1024);
+    let report = import::import(&db, &mut upload, config.submission_batch_size).await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
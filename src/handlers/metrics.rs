@@ -0,0 +1,45 @@
+use crate::{auth::RequireScope, db, metrics::Metrics};
+use actix_web::{get, web::Data, HttpResponse, Result};
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get metrics in Prometheus text format", content_type = "text/plain")
+    )
+)]
+#[get("/metrics", wrap = "RequireScope::read()")]
+pub async fn get_metrics(
+    db: Data<db::DBConnection>,
+    metrics: Data<Metrics>,
+) -> Result<HttpResponse> {
+    let stats = db.get_tasks_stats().await?;
+    for (status, count) in [
+        ("idle", stats.idle),
+        ("processing", stats.processing),
+        ("done", stats.done),
+        ("failed", stats.failed),
+        ("cancelled", stats.cancelled),
+    ] {
+        metrics.tasks.with_label_values(&[status]).set(count);
+    }
+
+    let pool = db.pool_stats();
+    metrics
+        .db_pool_connections
+        .with_label_values(&["active"])
+        .set(pool.size as i64 - pool.idle as i64);
+    metrics
+        .db_pool_connections
+        .with_label_values(&["idle"])
+        .set(pool.idle as i64);
+    metrics.db_pool_max_connections.set(pool.max as i64);
+
+    metrics.db_size.set(db.get_db_size_mb().await?);
+
+    let body = metrics
+        .encode()
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(metrics.content_type())
+        .body(body))
+}
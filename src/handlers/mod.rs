@@ -1,2 +1,4 @@
 pub mod archive;
+pub mod metrics;
 pub mod tasks;
+pub mod workers;
@@ -1,38 +1,191 @@
 use crate::{
+    auth::RequireScope,
+    config::Config,
     db,
-    models::{self, TaskSubmission},
+    file_upload::{NdjsonUpload, SpooledUpload},
+    handlers::workers::WorkerId,
+    metrics::Metrics,
+    models::{self, IngestionReport, NewPaperFull, RejectedPaper, TaskFailure, TaskHeartbeat},
+    validation,
 };
 use actix_multipart::Multipart;
 use actix_web::{
+    delete,
+    dev::Payload,
     get,
-    http::header::ContentType,
+    http::{header::ContentType, ConnectionType},
     post, put,
-    web::{Data, Path},
-    HttpResponse, Result,
+    web::{Data, Json, Path, Query},
+    FromRequest, HttpRequest, HttpResponse, Result,
 };
 use chrono::Datelike;
+use std::collections::BTreeSet;
+
+const MAX_TASKS_PER_CLAIM: i64 = 100;
+/// Waiting requests also look for tasks this often, as tasks which were
+/// postponed for retry become available without notification
+const WAIT_RECHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct GetTaskQuery {
+    /// Claim several tasks at once (max 100), an array is returned then
+    count: Option<i64>,
+    /// Wait for tasks if the queue is empty, e.g. `30s` or `1m`. Limited by
+    /// server configuration
+    wait: Option<String>,
+}
+
+/// Parse duration like `30s`, `1m` or just `30` seconds.
+fn parse_wait(wait: &str) -> Option<std::time::Duration> {
+    let (number, unit) = match wait.strip_suffix('m') {
+        Some(minutes) => (minutes, 60),
+        None => (wait.strip_suffix('s').unwrap_or(wait), 1),
+    };
+    let number = number.parse::<u64>().ok()?;
+    Some(std::time::Duration::from_secs(number.checked_mul(unit)?))
+}
 
 #[utoipa::path(
     responses(
-        (status = 200, description = "Get next task", body = NewTask),
+        (status = 200, description = "Get next task with a lease on it, or an array of tasks if count is given", body = TaskLease),
+        (status = 400, description = "Missing X-Worker-Id header or invalid wait"),
         (status = 404, description = "No tasks available")
-    )
+    ),
+    params(
+        GetTaskQuery,
+        ("X-Worker-Id" = String, Header, description = "Name of the scraper"),
+    ),
 )]
-#[get("/tasks")]
-pub async fn get_task(db: Data<db::DBConnection>) -> Result<HttpResponse> {
-    match db.get_next_task().await? {
-        Some(task) => {
-            let task = models::NewTask {
-                submission_date: task.submission_date,
-            };
-            let task = serde_json::to_string(&task)?;
-
-            Ok(HttpResponse::Ok()
-                .content_type(ContentType::json())
-                .body(task))
+#[get("/tasks", wrap = "RequireScope::scraper()")]
+pub async fn get_task(
+    db: Data<db::DBConnection>,
+    config: Data<Config>,
+    metrics: Data<Metrics>,
+    waiters: Data<db::TaskWaiters>,
+    worker: WorkerId,
+    query: Query<GetTaskQuery>,
+) -> Result<HttpResponse> {
+    let count = query.count.unwrap_or(1).clamp(1, MAX_TASKS_PER_CLAIM);
+    let wait = match query.wait.as_deref().map(parse_wait) {
+        Some(Some(wait)) => wait.min(std::time::Duration::from_secs(config.task_max_wait_seconds)),
+        Some(None) => return Ok(HttpResponse::BadRequest().body("Invalid wait duration")),
+        None => std::time::Duration::ZERO,
+    };
+    let deadline = tokio::time::Instant::now() + wait;
+
+    let tasks = loop {
+        // NOTE: subscribe before looking for tasks, otherwise notification
+        // sent in between is lost
+        let notified = waiters.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        let tasks = db
+            .get_next_tasks(
+                config.task_lease_seconds,
+                &worker.0,
+                config.task_dispatch_order,
+                count,
+            )
+            .await?;
+        let now = tokio::time::Instant::now();
+        if !tasks.is_empty() || now >= deadline {
+            break tasks;
         }
-        None => Ok(HttpResponse::NotFound().body("No tasks available")),
+
+        let recheck = deadline.min(now + WAIT_RECHECK_INTERVAL);
+        let _ = tokio::time::timeout_at(recheck, notified).await;
+    };
+    if tasks.is_empty() {
+        return Ok(HttpResponse::NotFound().body("No tasks available"));
+    }
+    metrics.tasks_handed_out.inc_by(tasks.len() as u64);
+
+    let mut leases = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        let models::Task {
+            submission_date,
+            category,
+            lease_id: Some(lease_id),
+            lease_expires_at: Some(lease_expires_at),
+            ..
+        } = task
+        else {
+            return Ok(HttpResponse::InternalServerError().body("Task was given without lease"));
+        };
+        leases.push(models::TaskLease {
+            submission_date,
+            category,
+            lease_id,
+            lease_expires_at,
+        });
     }
+
+    let body = match query.count {
+        Some(_) => serde_json::to_string(&leases)?,
+        None => serde_json::to_string(&leases[0])?,
+    };
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .body(body))
+}
+
+#[utoipa::path(
+    request_body = TaskHeartbeat,
+    responses(
+        (status = 200, description = "Lease extended", body = TaskLease),
+        (status = 409, description = "Lease is expired or held by someone else")
+    ),
+    params(
+        ("submission_date",),
+    ),
+)]
+#[post("/tasks/{submission_date}/heartbeat", wrap = "RequireScope::scraper()")]
+pub async fn heartbeat(
+    db: Data<db::DBConnection>,
+    config: Data<Config>,
+    path: Path<chrono::NaiveDate>,
+    heartbeat: Json<TaskHeartbeat>,
+) -> Result<HttpResponse> {
+    let submission_date = path.into_inner();
+    let TaskHeartbeat { lease_id } = heartbeat.into_inner();
+
+    let lease = db
+        .extend_lease(submission_date, lease_id, config.task_lease_seconds)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(lease))
+}
+
+#[utoipa::path(
+    request_body = TaskFailure,
+    responses(
+        (status = 200, description = "Failure recorded, task is either requeued or marked as failed"),
+        (status = 400, description = "Missing X-Worker-Id header"),
+        (status = 409, description = "Lease is expired or held by someone else")
+    ),
+    params(
+        ("submission_date",),
+        ("X-Worker-Id" = String, Header, description = "Name of the scraper"),
+    ),
+)]
+#[post("/tasks/{submission_date}/fail", wrap = "RequireScope::scraper()")]
+pub async fn fail_task(
+    db: Data<db::DBConnection>,
+    config: Data<Config>,
+    worker: WorkerId,
+    path: Path<chrono::NaiveDate>,
+    failure: Json<TaskFailure>,
+) -> Result<HttpResponse> {
+    let submission_date = path.into_inner();
+    let retry = db::RetryPolicy::from(&**config);
+
+    let status = db
+        .fail_task(submission_date, failure.into_inner(), &worker.0, &retry)
+        .await?;
+
+    Ok(HttpResponse::Ok().json(status))
 }
 
 #[utoipa::path(
@@ -40,37 +193,436 @@ pub async fn get_task(db: Data<db::DBConnection>) -> Result<HttpResponse> {
         (status = 200, description = "Get info about task queue", body = TasksStats)
     )
 )]
-#[get("/tasks/stats")]
+#[get("/tasks/stats", wrap = "RequireScope::read()")]
 pub async fn get_stats(db: Data<db::DBConnection>) -> Result<HttpResponse> {
     let stats = db.get_tasks_stats().await?;
 
     Ok(HttpResponse::Ok().json(stats))
 }
 
+/// Weeks before the range which are also used to learn the usual number of
+/// papers for every weekday
+const COVERAGE_NORM_WEEKS: i64 = 8;
+const MAX_COVERAGE_DAYS: i64 = 5 * 366;
+const DEFAULT_COVERAGE_MIN_RATIO: f64 = 0.5;
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct CoverageQuery {
+    /// First submission date, inclusive
+    from: chrono::NaiveDate,
+    /// Last submission date, inclusive
+    to: chrono::NaiveDate,
+    /// Day is flagged as low if it has less than this share of the usual
+    /// number of papers for its weekday (default 0.5)
+    min_ratio: Option<f64>,
+}
+
+impl CoverageQuery {
+    /// Coverage of every day in range with missing and low days flagged.
+    /// Returns None if the range is invalid.
+    async fn coverage(&self, db: &db::DBConnection) -> Result<Option<Vec<models::DayCoverage>>> {
+        if self.from > self.to || (self.to - self.from).num_days() >= MAX_COVERAGE_DAYS {
+            return Ok(None);
+        }
+
+        let norm_from = self
+            .to
+            .checked_sub_signed(chrono::Duration::weeks(COVERAGE_NORM_WEEKS))
+            .map_or(self.from, |norm_from| norm_from.min(self.from));
+        let mut days = db.get_coverage(norm_from, self.to).await?;
+
+        // Median of non-empty days for every weekday
+        let mut counts: [Vec<i64>; 7] = Default::default();
+        for day in days.iter().filter(|day| day.paper_count > 0) {
+            let weekday = day.submission_date.weekday().num_days_from_monday();
+            counts[weekday as usize].push(day.paper_count);
+        }
+        let norms = counts.map(|mut counts| {
+            counts.sort_unstable();
+            counts.get(counts.len() / 2).copied()
+        });
+
+        let min_ratio = self.min_ratio.unwrap_or(DEFAULT_COVERAGE_MIN_RATIO);
+        let today = chrono::Utc::now().date_naive();
+        days.retain(|day| day.submission_date >= self.from);
+        for day in &mut days {
+            let weekday = day.submission_date.weekday().num_days_from_monday();
+            day.expected_count = norms[weekday as usize];
+            // NOTE: papers of today are not all submitted yet
+            if day.submission_date < today {
+                day.missing = day.paper_count == 0;
+                day.low = !day.missing
+                    && day.expected_count.is_some_and(|expected| {
+                        (day.paper_count as f64) < expected as f64 * min_ratio
+                    });
+            }
+        }
+
+        Ok(Some(days))
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get number of papers and task status for every day in range, \
+                                      days without papers or with far less papers than usual are \
+                                      flagged", body = CoverageReport),
+        (status = 400, description = "Invalid range")
+    ),
+    params(CoverageQuery),
+)]
+#[get("/tasks/coverage", wrap = "RequireScope::read()")]
+pub async fn get_coverage(
+    db: Data<db::DBConnection>,
+    query: Query<CoverageQuery>,
+) -> Result<HttpResponse> {
+    let Some(days) = query.coverage(&db).await? else {
+        return Ok(HttpResponse::BadRequest().body("Invalid range"));
+    };
+
+    Ok(HttpResponse::Ok().json(models::CoverageReport {
+        days,
+        enqueued: Vec::new(),
+    }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Flagged days in range are put to the queue, unless they are \
+                                      already queued, cancelled or split by categories", body = CoverageReport),
+        (status = 400, description = "Invalid range")
+    ),
+    params(CoverageQuery),
+)]
+#[post("/tasks/coverage", wrap = "RequireScope::admin()")]
+pub async fn enqueue_coverage_gaps(
+    db: Data<db::DBConnection>,
+    query: Query<CoverageQuery>,
+) -> Result<HttpResponse> {
+    let Some(days) = query.coverage(&db).await? else {
+        return Ok(HttpResponse::BadRequest().body("Invalid range"));
+    };
+
+    let enqueued = days
+        .iter()
+        .filter(|day| (day.missing || day.low) && !day.split)
+        .filter(|day| {
+            !matches!(
+                day.status,
+                Some(models::Status::Idle | models::Status::Processing | models::Status::Cancelled)
+            )
+        })
+        .map(|day| day.submission_date)
+        .collect::<Vec<_>>();
+    if !enqueued.is_empty() {
+        db.requeue_tasks(&enqueued).await?;
+    }
+
+    Ok(HttpResponse::Ok().json(models::CoverageReport { days, enqueued }))
+}
+
+const DEFAULT_TASKS_LIMIT: i64 = 100;
+const MAX_TASKS_LIMIT: i64 = 1000;
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct TaskCategoryQuery {
+    /// arXiv category of the task, the whole day task if missing
+    category: Option<String>,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get task with all its details", body = Task),
+        (status = 404, description = "Task not found")
+    ),
+    params(
+        ("submission_date",),
+        TaskCategoryQuery,
+    ),
+)]
+#[get("/tasks/{submission_date}", wrap = "RequireScope::read()")]
+pub async fn get_task_info(
+    db: Data<db::DBConnection>,
+    path: Path<chrono::NaiveDate>,
+    query: Query<TaskCategoryQuery>,
+) -> Result<HttpResponse> {
+    match db
+        .get_task(path.into_inner(), query.category.as_deref())
+        .await?
+    {
+        Some(task) => Ok(HttpResponse::Ok().json(task)),
+        None => Ok(HttpResponse::NotFound().body("Task not found")),
+    }
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct ListTasksQuery {
+    /// Only tasks with this status
+    status: Option<models::Status>,
+    /// Only tasks for this arXiv category, empty for whole day tasks
+    category: Option<String>,
+    /// First submission date, inclusive
+    from: Option<chrono::NaiveDate>,
+    /// Last submission date, inclusive
+    to: Option<chrono::NaiveDate>,
+    /// Number of tasks to return (default 100, max 1000)
+    limit: Option<i64>,
+    /// Number of tasks to skip
+    offset: Option<i64>,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Tasks ordered by submission date", body = TasksPage),
+    ),
+    params(
+        ListTasksQuery,
+    ),
+)]
+#[get("/tasks/list", wrap = "RequireScope::read()")]
+pub async fn list_tasks(
+    db: Data<db::DBConnection>,
+    query: Query<ListTasksQuery>,
+) -> Result<HttpResponse> {
+    let ListTasksQuery {
+        status,
+        category,
+        from,
+        to,
+        limit,
+        offset,
+    } = query.into_inner();
+    let limit = limit
+        .unwrap_or(DEFAULT_TASKS_LIMIT)
+        .clamp(1, MAX_TASKS_LIMIT);
+    let offset = offset.unwrap_or(0).max(0);
+
+    let (total, tasks) = db
+        .list_tasks(
+            status,
+            category.as_deref(),
+            &db::DateRange { from, to },
+            limit,
+            offset,
+        )
+        .await?;
+
+    Ok(HttpResponse::Ok().json(models::TasksPage { total, tasks }))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Done, failed or cancelled task is put back to the queue", body = Task),
+        (status = 404, description = "Task not found"),
+        (status = 409, description = "Task is idle or being processed")
+    ),
+    params(
+        ("submission_date",),
+        TaskCategoryQuery,
+    ),
+)]
+#[post("/tasks/{submission_date}/reset", wrap = "RequireScope::admin()")]
+pub async fn reset_task(
+    db: Data<db::DBConnection>,
+    path: Path<chrono::NaiveDate>,
+    query: Query<TaskCategoryQuery>,
+) -> Result<HttpResponse> {
+    match db
+        .reset_task(path.into_inner(), query.category.as_deref())
+        .await?
+    {
+        Some(task) => Ok(HttpResponse::Ok().json(task)),
+        None => Ok(HttpResponse::NotFound().body("Task not found")),
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get papers of rejected submissions of the day kept for review, \
+                                      they are kept only if quarantine is enabled", body = [QuarantinedPaper])
+    ),
+    params(
+        ("submission_date",),
+    ),
+)]
+#[get("/tasks/{submission_date}/quarantine", wrap = "RequireScope::read()")]
+pub async fn get_quarantine(
+    db: Data<db::DBConnection>,
+    path: Path<chrono::NaiveDate>,
+) -> Result<HttpResponse> {
+    let papers = db.get_quarantine(path.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(papers))
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct TaskRangeQuery {
+    /// First submission date, inclusive
+    from: chrono::NaiveDate,
+    /// Last submission date, inclusive
+    to: chrono::NaiveDate,
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Idle, processing and failed tasks in range are cancelled, \
+                                      done tasks are skipped", body = TaskRangeReport),
+        (status = 400, description = "Invalid range")
+    ),
+    params(TaskRangeQuery),
+)]
+#[post("/tasks/cancel", wrap = "RequireScope::admin()")]
+pub async fn cancel_tasks(
+    db: Data<db::DBConnection>,
+    query: Query<TaskRangeQuery>,
+) -> Result<HttpResponse> {
+    let TaskRangeQuery { from, to } = query.into_inner();
+    if from > to {
+        return Ok(HttpResponse::BadRequest().body("Invalid range"));
+    }
+
+    let report = db
+        .cancel_tasks(&db::DateRange {
+            from: Some(from),
+            to: Some(to),
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Tasks in range are deleted, the ones being processed are \
+                                      skipped", body = TaskRangeReport),
+        (status = 400, description = "Invalid range")
+    ),
+    params(TaskRangeQuery),
+)]
+#[delete("/tasks", wrap = "RequireScope::admin()")]
+pub async fn delete_tasks(
+    db: Data<db::DBConnection>,
+    query: Query<TaskRangeQuery>,
+) -> Result<HttpResponse> {
+    let TaskRangeQuery { from, to } = query.into_inner();
+    if from > to {
+        return Ok(HttpResponse::BadRequest().body("Invalid range"));
+    }
+
+    let report = db
+        .delete_tasks(&db::DateRange {
+            from: Some(from),
+            to: Some(to),
+        })
+        .await?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[utoipa::path(
+    responses(
+        (status = 204, description = "Task deleted"),
+        (status = 404, description = "Task not found"),
+        (status = 409, description = "Task is being processed")
+    ),
+    params(
+        ("submission_date",),
+        TaskCategoryQuery,
+    ),
+)]
+#[delete("/tasks/{submission_date}", wrap = "RequireScope::admin()")]
+pub async fn delete_task(
+    db: Data<db::DBConnection>,
+    path: Path<chrono::NaiveDate>,
+    query: Query<TaskCategoryQuery>,
+) -> Result<HttpResponse> {
+    match db
+        .delete_task(path.into_inner(), query.category.as_deref())
+        .await?
+    {
+        Some(()) => Ok(HttpResponse::NoContent().into()),
+        None => Ok(HttpResponse::NotFound().body("Task not found")),
+    }
+}
+
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct TaskCreationQuery {
+    /// Tasks with higher priority are given to scrapers first (default 0).
+    /// Also changes priority of already existing idle tasks
+    priority: Option<i32>,
+    /// Comma separated arXiv categories, e.g. `cs.LG,hep-th`. A task per day
+    /// and category is created, one task per whole day if missing
+    categories: Option<String>,
+}
+
+impl TaskCreationQuery {
+    /// Distinct categories of the tasks to create, None stands for the whole
+    /// day. Returns None if any of the categories is malformed.
+    fn categories(&self) -> Option<Vec<Option<String>>> {
+        let Some(categories) = &self.categories else {
+            return Some(vec![None]);
+        };
+
+        let categories = categories
+            .split(',')
+            .map(str::trim)
+            .filter(|category| !category.is_empty())
+            .map(|category| {
+                category
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-')
+                    .then(|| category.to_string())
+            })
+            .collect::<Option<BTreeSet<_>>>()?;
+        (!categories.is_empty()).then(|| categories.into_iter().map(Some).collect())
+    }
+
+    fn new_tasks(
+        &self,
+        days: impl IntoIterator<Item = chrono::NaiveDate>,
+    ) -> Option<Vec<models::NewTask>> {
+        let categories = self.categories()?;
+        let tasks = days
+            .into_iter()
+            .flat_map(|submission_date| {
+                categories.iter().map(move |category| models::NewTask {
+                    submission_date,
+                    category: category.clone(),
+                })
+            })
+            .collect();
+        Some(tasks)
+    }
+}
+
 #[utoipa::path(
     responses(
         (status = 201, description = "Create new task to scrape specific day"),
-        (status = 400, description = "Invalid date")
+        (status = 400, description = "Invalid date or category")
     ),
     params(
         ("year",),
         ("month",),
-        ("day",)
+        ("day",),
+        TaskCreationQuery,
     ),
 )]
-#[post("/tasks/{year}/{month}/{day}")]
+#[post("/tasks/{year}/{month}/{day}", wrap = "RequireScope::admin()")]
 pub async fn post_day_as_task(
     db: Data<db::DBConnection>,
     date: Path<(i32, u32, u32)>,
+    query: Query<TaskCreationQuery>,
 ) -> Result<HttpResponse> {
     let (year, month, day) = date.into_inner();
 
     let Some(submission_date) = chrono::NaiveDate::from_ymd_opt(year, month, day) else {
         return Ok(HttpResponse::BadRequest().into());
     };
-    let new_task = models::NewTask { submission_date };
+    let Some(tasks) = query.new_tasks([submission_date]) else {
+        return Ok(HttpResponse::BadRequest().body("Invalid category"));
+    };
 
-    db.insert_task(vec![new_task]).await?;
+    db.insert_task(tasks, query.priority).await?;
 
     Ok(HttpResponse::Created().into())
 }
@@ -78,37 +630,32 @@ pub async fn post_day_as_task(
 #[utoipa::path(
     responses(
         (status = 201, description = "Create new tasks to scrape specific month"),
-        (status = 400, description = "Invalid date")
+        (status = 400, description = "Invalid date or category")
     ),
     params(
         ("year",),
         ("month",),
+        TaskCreationQuery,
     ),
 )]
-#[post("/tasks/{year}/{month}")]
+#[post("/tasks/{year}/{month}", wrap = "RequireScope::admin()")]
 pub async fn post_month_as_task(
     db: Data<db::DBConnection>,
     date: Path<(i32, u32)>,
+    query: Query<TaskCreationQuery>,
 ) -> Result<HttpResponse> {
     let (year, month) = date.into_inner();
 
-    let mut tasks = Vec::new();
-
     let Some(iter_days) = chrono::NaiveDate::from_ymd_opt(year, month, 1) else {
         return Ok(HttpResponse::BadRequest().body("Invalid date"));
     };
 
-    for day in iter_days.iter_days() {
-        if day.month() != month {
-            break;
-        }
-        let new_task = models::NewTask {
-            submission_date: day,
-        };
-        tasks.push(new_task);
-    }
+    let days = iter_days.iter_days().take_while(|day| day.month() == month);
+    let Some(tasks) = query.new_tasks(days) else {
+        return Ok(HttpResponse::BadRequest().body("Invalid category"));
+    };
 
-    db.insert_task(tasks).await?;
+    db.insert_task(tasks, query.priority).await?;
 
     Ok(HttpResponse::Created().into())
 }
@@ -116,68 +663,339 @@ pub async fn post_month_as_task(
 #[utoipa::path(
     responses(
         (status = 201, description = "Create new tasks to scrape specific year"),
-        (status = 400, description = "Invalid date")
+        (status = 400, description = "Invalid date or category")
     ),
     params(
         ("year",),
+        TaskCreationQuery,
     ),
 )]
-#[post("/tasks/{year}")]
+// NOTE: digits only, otherwise metrics attribute "/tasks/{submission_date}" requests to this route
+#[post("/tasks/{year:\\d+}", wrap = "RequireScope::admin()")]
 pub async fn post_year_as_task(
     db: Data<db::DBConnection>,
     date: Path<i32>,
+    query: Query<TaskCreationQuery>,
 ) -> Result<HttpResponse> {
     let year = date.into_inner();
 
-    let mut tasks = Vec::new();
-
     let Some(iter_days) = chrono::NaiveDate::from_ymd_opt(year, 1, 1) else {
         return Ok(HttpResponse::BadRequest().body("Invalid date"));
     };
 
-    for day in iter_days.iter_days() {
-        if day.year() != year {
-            break;
-        }
-        let new_task = models::NewTask {
-            submission_date: day,
+    let days = iter_days.iter_days().take_while(|day| day.year() == year);
+    let Some(tasks) = query.new_tasks(days) else {
+        return Ok(HttpResponse::BadRequest().body("Invalid category"));
+    };
+
+    db.insert_task(tasks, query.priority).await?;
+
+    Ok(HttpResponse::Created().into())
+}
+
+const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Rejected submission lists errors of this many invalid papers at most
+const MAX_REPORTED_INVALID_PAPERS: usize = 100;
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Key given by scraper to recognize retried submissions.
+pub struct IdempotencyKey(pub Option<String>);
+
+impl FromRequest for IdempotencyKey {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<std::result::Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(key) = req.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+            return std::future::ready(Ok(IdempotencyKey(None)));
         };
-        tasks.push(new_task);
+
+        std::future::ready(match key.to_str().map(str::trim) {
+            Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => {
+                Ok(IdempotencyKey(Some(key.to_string())))
+            }
+            _ => Err(actix_web::error::ErrorBadRequest(format!(
+                "Invalid {IDEMPOTENCY_KEY_HEADER} header"
+            ))),
+        })
     }
+}
 
-    db.insert_task(tasks).await?;
+#[derive(Debug, serde::Deserialize, utoipa::IntoParams)]
+pub struct SubmissionQuery {
+    /// Lease given with the task
+    lease_id: uuid::Uuid,
+    /// Ingest a submission of a task which was already submitted with
+    /// different content. New papers are added and newer versions update
+    /// archived ones, papers of the earlier submission are kept
+    #[serde(default)]
+    force: bool,
+}
 
-    Ok(HttpResponse::Created().into())
+/// Submissions without idempotency key are identified by their content.
+fn content_key(upload: &SpooledUpload) -> String {
+    format!("sha256:{}", upload.digest)
+}
+
+/// Response to a submission of already submitted task: report of the
+/// accepted submission if it is repeated, conflict otherwise. None if the
+/// task is to be submitted.
+fn replay_submission(
+    task: &db::LeasedTask,
+    key: &str,
+    submission_date: chrono::NaiveDate,
+    worker: &WorkerId,
+    force: bool,
+) -> Option<HttpResponse> {
+    if task.status != models::Status::Done || force {
+        return None;
+    }
+
+    match (&task.submission_key, &task.submission_report) {
+        (Some(submitted_key), Some(report)) if submitted_key == key => {
+            log::info!(
+                "Task {submission_date} submission by {:?} replayed",
+                worker.0
+            );
+            Some(
+                HttpResponse::Created()
+                    .content_type(ContentType::json())
+                    .body(report.clone()),
+            )
+        }
+        _ => Some(HttpResponse::from_error(db::Error::AlreadySubmitted(
+            submission_date,
+        ))),
+    }
+}
+
+/// Answer a submission before its upload is received. Connection is closed,
+/// otherwise the client would keep sending the upload which nobody reads.
+fn answer_unread(mut response: HttpResponse) -> HttpResponse {
+    response
+        .head_mut()
+        .set_connection_type(ConnectionType::Close);
+    response
 }
 
 #[utoipa::path(
-    request_body = TaskSubmission,
+    request_body(
+        content = String,
+        content_type = "multipart/form-data",
+        description = "File with one NewPaperFull JSON object per line"
+    ),
     responses(
-        (status = 201, description = "Task submitted successfully"),
-        (status = 400, description = "Invalid task result")
+        (status = 201, description = "Task submitted successfully. Repeated submission with the same \
+                                      idempotency key or content is not ingested again, report of the \
+                                      first one is returned", body = IngestionReport),
+        (status = 400, description = "Some of the papers are invalid and nothing is stored, \
+                                      invalid upload or missing X-Worker-Id header", body = SubmissionErrors),
+        (status = 409, description = "Lease is expired or held by someone else, or the task is already \
+                                      submitted with different content"),
+        (status = 413, description = "Submission is too large")
     ),
     params(
         ("submission_date",),
+        SubmissionQuery,
+        ("X-Worker-Id" = String, Header, description = "Name of the scraper"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Identifies the submission, \
+                                                                     hash of the file is used if missing"),
     ),
 )]
-#[put("/tasks/{submission_date}")]
+#[put("/tasks/{submission_date}", wrap = "RequireScope::scraper()")]
+#[allow(clippy::too_many_arguments)]
 pub async fn submit_task(
     db: Data<db::DBConnection>,
-    path: Path<String>,
+    config: Data<Config>,
+    metrics: Data<Metrics>,
+    worker: WorkerId,
+    path: Path<chrono::NaiveDate>,
+    query: Query<SubmissionQuery>,
+    idempotency_key: IdempotencyKey,
     payload: Multipart,
 ) -> Result<HttpResponse> {
+    let started = std::time::Instant::now();
     let submission_date = path.into_inner();
-    crate::file_upload::save_file(payload, submission_date.clone())
-        .await
-        .unwrap();
 
-    let mut storage = crate::file_upload::UPLOAD_STORAGE.lock().await;
-    let Some(data) = storage.remove(&submission_date) else {
-        return Ok(HttpResponse::InternalServerError().body("Upload not found in storage"));
+    let task = match db.get_leased_task(submission_date, query.lease_id).await {
+        Ok(task) => task,
+        Err(e) => return Ok(answer_unread(HttpResponse::from_error(e))),
+    };
+    // NOTE: retry with idempotency key is answered before the upload is received
+    if let Some(response) = idempotency_key
+        .0
+        .as_deref()
+        .and_then(|key| replay_submission(&task, key, submission_date, &worker, query.force))
+    {
+        return Ok(answer_unread(response));
+    }
+
+    // NOTE: upload is received in full before the task is locked, so that a slow
+    // scraper does not hold the lock and a database connection
+    let upload = NdjsonUpload::new(payload, config.max_submission_size_mb * 1024 * 1024)
+        .spool()
+        .await?;
+    let key = match idempotency_key.0 {
+        Some(key) => key,
+        None => {
+            let key = content_key(&upload);
+            if let Some(response) =
+                replay_submission(&task, &key, submission_date, &worker, query.force)
+            {
+                return Ok(response);
+            }
+            key
+        }
     };
-    let submission: TaskSubmission = serde_json::from_slice(&data)?;
+    let category = task.category;
 
-    db.submit_task(submission).await?;
+    let mut invalid = Vec::new();
+    let mut invalid_count = 0;
+    let mut papers = upload.reader().await?;
+    loop {
+        let records = papers
+            .next_batch::<NewPaperFull>(config.submission_batch_size)
+            .await?;
+        if records.is_empty() {
+            break;
+        }
 
-    Ok(HttpResponse::Created().into())
+        let mut batch_invalid = Vec::new();
+        validation::check_records(
+            records,
+            submission_date,
+            &mut batch_invalid,
+            config.quarantine_enabled,
+        );
+        if config.quarantine_enabled && !batch_invalid.is_empty() {
+            db.insert_quarantine(
+                submission_date,
+                category.as_deref(),
+                &worker.0,
+                &batch_invalid,
+            )
+            .await?;
+        }
+        invalid_count += batch_invalid.len();
+        let room = MAX_REPORTED_INVALID_PAPERS.saturating_sub(invalid.len());
+        invalid.extend(
+            batch_invalid
+                .into_iter()
+                .take(room)
+                .map(|paper| models::InvalidPaper {
+                    paper: None,
+                    ..paper
+                }),
+        );
+    }
+    if invalid_count > 0 {
+        log::info!(
+            "Task {submission_date} submission by {:?} rejected: {invalid_count} invalid papers",
+            worker.0,
+        );
+        return Ok(HttpResponse::BadRequest().json(models::SubmissionErrors {
+            total: invalid_count,
+            papers: invalid,
+        }));
+    }
+
+    let (mut tx, task) = db.begin_submission(submission_date, query.lease_id).await?;
+    // NOTE: the same submission could be accepted while this one was received
+    if let Some(response) = replay_submission(&task, &key, submission_date, &worker, query.force) {
+        return Ok(response);
+    }
+
+    let mut report = IngestionReport::default();
+    let mut papers = upload.reader().await?;
+    loop {
+        let records = papers
+            .next_batch::<NewPaperFull>(config.submission_batch_size)
+            .await?;
+        if records.is_empty() {
+            break;
+        }
+
+        // NOTE: papers are already validated, so nothing is invalid here
+        let mut papers = validation::check_records(records, submission_date, &mut invalid, false);
+        if let Some(category) = &category {
+            papers.retain(|paper| {
+                let in_category = paper
+                    .subjects
+                    .iter()
+                    .any(|subject| &subject.name == category);
+                if !in_category {
+                    report.rejected.push(RejectedPaper {
+                        arxiv_id: Some(paper.arxiv_id.clone()),
+                        reason: format!("Paper is not in task category {category}"),
+                    });
+                }
+                in_category
+            });
+        }
+        db.insert_papers_full(papers, &mut report, &mut tx).await?;
+    }
+    db.finish_submission(
+        tx,
+        submission_date,
+        query.lease_id,
+        &worker.0,
+        &key,
+        &report,
+    )
+    .await?;
+
+    metrics.tasks_submitted.inc();
+    metrics.submission_bytes.observe(upload.size as f64);
+    metrics
+        .submission_papers
+        .observe((report.inserted + report.updated + report.skipped.len()) as f64);
+    metrics
+        .ingest_duration
+        .observe(started.elapsed().as_secs_f64());
+
+    log::info!(
+        "Task {submission_date} {} submitted by {:?}: {} inserted, {} skipped, {} rejected",
+        category.as_deref().unwrap_or("(whole day)"),
+        worker.0,
+        report.inserted,
+        report.skipped.len(),
+        report.rejected.len()
+    );
+
+    Ok(HttpResponse::Created().json(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+
+    #[sqlx::test]
+    async fn repeated_category_creates_one_task(pool: sqlx::PgPool) {
+        let db = db::DBConnection::from_pool(pool);
+        let config = Config {
+            auth_enabled: false,
+            ..Config::default()
+        };
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(db.clone()))
+                .app_data(Data::new(config))
+                .service(post_day_as_task),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/tasks/2024/1/2?categories=cs.LG,cs.LG")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), actix_web::http::StatusCode::CREATED);
+
+        let (total, tasks) = db
+            .list_tasks(None, None, &db::DateRange::default(), 10, 0)
+            .await
+            .unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(tasks[0].category.as_deref(), Some("cs.LG"));
+    }
 }
@@ -0,0 +1,41 @@
+use crate::{auth::RequireScope, db};
+use actix_web::{dev::Payload, get, web::Data, FromRequest, HttpRequest, HttpResponse, Result};
+
+pub const WORKER_ID_HEADER: &str = "X-Worker-Id";
+const MAX_WORKER_ID_LEN: usize = 64;
+
+/// Name of the scraper making the request, taken from `X-Worker-Id` header.
+pub struct WorkerId(pub String);
+
+impl FromRequest for WorkerId {
+    type Error = actix_web::Error;
+    type Future = std::future::Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let worker = req
+            .headers()
+            .get(WORKER_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty() && value.len() <= MAX_WORKER_ID_LEN);
+
+        std::future::ready(match worker {
+            Some(worker) => Ok(WorkerId(worker.to_string())),
+            None => Err(actix_web::error::ErrorBadRequest(format!(
+                "Missing or invalid {WORKER_ID_HEADER} header"
+            ))),
+        })
+    }
+}
+
+#[utoipa::path(
+    responses(
+        (status = 200, description = "Get all known workers and their activity", body = [Worker])
+    )
+)]
+#[get("/workers", wrap = "RequireScope::read()")]
+pub async fn get_workers(db: Data<db::DBConnection>) -> Result<HttpResponse> {
+    let workers = db.get_workers().await?;
+
+    Ok(HttpResponse::Ok().json(workers))
+}
@@ -0,0 +1,99 @@
+use crate::{
+    db,
+    file_upload::{self, NdjsonUpload, Record},
+    models::{IngestionReport, NewPaperFull, RejectedPaper},
+    validation,
+};
+use std::collections::BTreeSet;
+
+/// Recorded as `submitted_by` of tasks completed by import
+pub const IMPORTED_BY: &str = "import";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Db(#[from] db::Error),
+    #[error(transparent)]
+    Upload(#[from] file_upload::Error),
+}
+impl actix_web::error::ResponseError for Error {
+    fn status_code(&self) -> actix_web::http::StatusCode {
+        match self {
+            Error::Db(e) => e.status_code(),
+            Error::Upload(e) => e.status_code(),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Take successfully parsed valid papers, reporting malformed lines and
+/// invalid papers as rejected.
+pub fn accept_records(
+    records: Vec<Record<NewPaperFull>>,
+    report: &mut IngestionReport,
+) -> Vec<NewPaperFull> {
+    let mut papers = Vec::with_capacity(records.len());
+    for record in records {
+        let paper = match record.value {
+            Ok(paper) => paper,
+            Err(e) => {
+                report.rejected.push(RejectedPaper {
+                    arxiv_id: None,
+                    reason: format!("Line {}: {e}", record.line),
+                });
+                continue;
+            }
+        };
+
+        let errors = validation::check_paper(&paper, None);
+        if errors.is_empty() {
+            papers.push(paper);
+        } else {
+            report.rejected.push(RejectedPaper {
+                arxiv_id: Some(paper.arxiv_id),
+                reason: format!("Line {}: {}", record.line, errors.join(", ")),
+            });
+        }
+    }
+    papers
+}
+
+/// Ingest NDJSON of `NewPaperFull` (e.g. made by `/archive/export`) and mark
+/// days it covers as done. Every batch is committed separately, so a failed
+/// import can be resumed by running it again.
+pub async fn import(
+    db: &db::DBConnection,
+    upload: &mut NdjsonUpload,
+    batch_size: usize,
+) -> Result<IngestionReport> {
+    let mut report = IngestionReport::default();
+    loop {
+        let records = upload.next_batch::<NewPaperFull>(batch_size).await?;
+        if records.is_empty() {
+            break;
+        }
+
+        let papers = accept_records(records, &mut report);
+        let submission_dates = papers
+            .iter()
+            .map(|paper| paper.submission_date)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        let mut tx = db.begin_import().await?;
+        db.insert_papers_full(papers, &mut report, &mut tx).await?;
+        db.finish_import(tx, &submission_dates, IMPORTED_BY).await?;
+    }
+
+    log::info!(
+        "Import finished: {} inserted, {} updated, {} skipped, {} rejected",
+        report.inserted,
+        report.updated,
+        report.skipped.len(),
+        report.rejected.len()
+    );
+
+    Ok(report)
+}
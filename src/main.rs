@@ -1,21 +1,32 @@
+mod auth;
+mod calendar;
+mod cli;
 mod config;
 mod db;
 mod file_upload;
 mod handlers;
+mod import;
+mod metrics;
 mod models;
 mod routes;
+mod validation;
 
 use actix_web::{
+    dev::Service,
     middleware::{Compress, Logger},
     web::{Data, JsonConfig},
     App, HttpServer,
 };
-use clokwerk::TimeUnits;
+use clap::Parser;
+use clokwerk::{Job, TimeUnits};
 use config::Config;
 use figment::{providers::Env, Figment};
+use std::path::Path;
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
+    let cli = cli::Cli::parse();
+
     // Load environment variables from .env if it exists
     _ = dotenvy::dotenv();
 
@@ -25,24 +36,126 @@ async fn main() -> anyhow::Result<()> {
         .extract()?;
 
     // Initialize logger
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or(config.log_level));
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or(&config.log_level));
 
     // Connect to database
     let db_url = std::env::var("DATABASE_URL")?;
-    let db = std::sync::Arc::new(db::DBConnection::new(&db_url).await?);
+    let db = db::DBConnection::new(&db_url).await?;
+
+    match cli.command.unwrap_or_default() {
+        cli::Command::Serve => serve(config, db).await,
+        cli::Command::Import { file } => import_file(&config, &db, &file).await,
+        cli::Command::Keys { command } => manage_keys(&db, command).await,
+    }
+}
+
+async fn manage_keys(db: &db::DBConnection, command: cli::KeysCommand) -> anyhow::Result<()> {
+    match command {
+        cli::KeysCommand::Create { name, scope } => {
+            let key = auth::generate_key();
+            db.insert_api_key(&name, &auth::hash_key(&key), scope)
+                .await?;
+            eprintln!("Created {scope:?} key {name:?}, it will not be shown again:");
+            println!("{key}");
+        }
+        cli::KeysCommand::List => {
+            for key in db.get_api_keys().await? {
+                println!("{}\t{:?}\t{}", key.name, key.scope, key.created_at);
+            }
+        }
+        cli::KeysCommand::Revoke { name } => {
+            if !db.delete_api_key(&name).await? {
+                anyhow::bail!("No API key named {name:?}");
+            }
+            eprintln!("Revoked key {name:?}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn import_file(config: &Config, db: &db::DBConnection, file: &Path) -> anyhow::Result<()> {
+    let mut upload = if file == Path::new("-") {
+        file_upload::NdjsonUpload::from_reader(tokio::io::stdin())
+    } else {
+        file_upload::NdjsonUpload::from_reader(tokio::fs::File::open(file).await?)
+    };
+    // NOTE: multipart errors are not Send, so anyhow can not wrap import::Error directly
+    let report = import::import(db, &mut upload, config.submission_batch_size)
+        .await
+        .map_err(|e| anyhow::anyhow!("{e}"))?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    Ok(())
+}
+
+/// Enqueue new announcement days every morning and scrape recent ones again
+/// from time to time, as papers are sometimes added late.
+fn schedule_enqueue(
+    scheduler: &mut clokwerk::AsyncScheduler<chrono::Utc>,
+    config: &Config,
+    db: std::sync::Arc<db::DBConnection>,
+) {
+    let calendar = std::sync::Arc::new(calendar::AnnouncementCalendar::from(config));
+
+    let (db_cloned, calendar_cloned) = (db.clone(), calendar.clone());
+    let days = config.auto_enqueue_days;
+    scheduler
+        .every(1.day())
+        .at_time(config.auto_enqueue_at)
+        .run(move || {
+            let db = db_cloned.clone();
+            let calendar = calendar_cloned.clone();
+            async move {
+                let today = chrono::Utc::now().date_naive();
+                match db.enqueue_days(&calendar.days_before(today, days)).await {
+                    Ok(created) => log::info!("Enqueued {created} latest announcement days"),
+                    Err(e) => log::error!("Could not enqueue latest announcement days: {e}"),
+                }
+            }
+        });
+
+    let days = config.auto_requeue_days;
+    scheduler
+        .every(config.auto_requeue_every_days.days())
+        .at_time(config.auto_enqueue_at)
+        .run(move || {
+            let db = db.clone();
+            let calendar = calendar.clone();
+            async move {
+                let today = chrono::Utc::now().date_naive();
+                match db.requeue_tasks(&calendar.days_before(today, days)).await {
+                    Ok(queued) => log::info!("Requeued {queued} recent announcement days"),
+                    Err(e) => log::error!("Could not requeue recent announcement days: {e}"),
+                }
+            }
+        });
+}
+
+async fn serve(config: Config, db: db::DBConnection) -> anyhow::Result<()> {
+    let db = std::sync::Arc::new(db);
     let db_cloned = db.clone();
+    let metrics = Data::new(metrics::Metrics::new()?);
+    let metrics_cloned = metrics.clone();
 
     // Create and start scheduler
-    let mut scheduler = clokwerk::AsyncScheduler::new();
+    let mut scheduler = clokwerk::AsyncScheduler::with_tz(chrono::Utc);
+    let retry = std::sync::Arc::new(db::RetryPolicy::from(&config));
     scheduler
-        .every(config.task_duration_check_seconds.seconds())
+        .every(config.task_lease_check_seconds.seconds())
         .run(move || {
-            let threshold = config.task_duration_threshold_seconds;
             let db = db_cloned.clone();
+            let retry = retry.clone();
+            let metrics = metrics_cloned.clone();
             async move {
-                let _ = db.revert_long_running_tasks(threshold).await;
+                if let Ok(reverted) = db.revert_expired_leases(&retry).await {
+                    metrics.tasks_reverted.inc_by(reverted as u64);
+                }
             }
         });
+    if config.auto_enqueue_enabled {
+        schedule_enqueue(&mut scheduler, &config, db.clone());
+    }
     tokio::spawn(async move {
         loop {
             scheduler.run_pending().await;
@@ -50,16 +163,57 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
+    // Wake up scrapers waiting for tasks
+    let waiters = Data::new(db::TaskWaiters::default());
+    let (db_cloned, waiters_cloned) = (db.clone(), waiters.clone());
+    tokio::spawn(async move {
+        if let Err(e) = db_cloned.listen_for_tasks(&waiters_cloned).await {
+            log::error!("Could not listen for tasks notifications: {e}");
+        }
+    });
+
     // Start web server
+    let (addr, port) = (config.addr.clone(), config.port);
+    let config = Data::new(config);
     HttpServer::new(move || {
         App::new()
             .wrap(Compress::default())
             .app_data(Data::new((*db).clone()))
+            .app_data(config.clone())
+            .app_data(metrics.clone())
+            .app_data(waiters.clone())
             .app_data(JsonConfig::default().limit(1024 * 1024 * 1024))
             .configure(routes::routes)
+            .wrap_fn({
+                let metrics = metrics.clone();
+                move |req, srv| {
+                    let metrics = metrics.clone();
+                    let started = std::time::Instant::now();
+                    let method = req.method().to_string();
+                    // NOTE: route pattern instead of path keeps number of series bounded
+                    let route = req
+                        .match_pattern()
+                        .unwrap_or_else(|| "unmatched".to_string());
+                    let res = srv.call(req);
+                    async move {
+                        let res = res.await;
+                        // NOTE: requests rejected by middleware, e.g. without API key, end
+                        // up as errors
+                        let status = match &res {
+                            Ok(res) => res.status(),
+                            Err(e) => e.as_response_error().status_code(),
+                        };
+                        metrics
+                            .http_requests
+                            .with_label_values(&[&method, &route, status.as_str()])
+                            .observe(started.elapsed().as_secs_f64());
+                        res
+                    }
+                }
+            })
             .wrap(Logger::default())
     })
-    .bind((config.addr, config.port))?
+    .bind((addr, port))?
     .run()
     .await?;
 
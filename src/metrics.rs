@@ -0,0 +1,104 @@
+use prometheus::{
+    exponential_buckets, Encoder, Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+/// Prometheus metrics of archivist. Counters and histograms are updated as
+/// things happen, gauges are refreshed from database on every scrape.
+pub struct Metrics {
+    registry: Registry,
+    pub tasks: IntGaugeVec,
+    pub tasks_handed_out: IntCounter,
+    pub tasks_submitted: IntCounter,
+    pub tasks_reverted: IntCounter,
+    pub submission_bytes: Histogram,
+    pub submission_papers: Histogram,
+    pub ingest_duration: Histogram,
+    pub http_requests: HistogramVec,
+    pub db_pool_connections: IntGaugeVec,
+    pub db_pool_max_connections: IntGauge,
+    pub db_size: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("archivist".to_string()), None)?;
+
+        let tasks = IntGaugeVec::new(
+            Opts::new("tasks", "Number of tasks in each status"),
+            &["status"],
+        )?;
+        let tasks_handed_out =
+            IntCounter::new("tasks_handed_out_total", "Tasks leased to scrapers")?;
+        let tasks_submitted =
+            IntCounter::new("tasks_submitted_total", "Tasks submitted by scrapers")?;
+        let tasks_reverted = IntCounter::new(
+            "tasks_reverted_total",
+            "Tasks taken back from scrapers because of expired lease",
+        )?;
+        let submission_bytes = Histogram::with_opts(
+            HistogramOpts::new("submission_bytes", "Size of task submissions")
+                .buckets(exponential_buckets(1024., 4., 10)?),
+        )?;
+        let submission_papers = Histogram::with_opts(
+            HistogramOpts::new("submission_papers", "Number of papers in task submissions")
+                .buckets(exponential_buckets(1., 2., 14)?),
+        )?;
+        let ingest_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "ingest_duration_seconds",
+                "Time spent ingesting task submissions",
+            )
+            .buckets(exponential_buckets(0.05, 2., 12)?),
+        )?;
+        let http_requests = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency"),
+            &["method", "route", "status"],
+        )?;
+        let db_pool_connections = IntGaugeVec::new(
+            Opts::new("db_pool_connections", "Database pool connections"),
+            &["state"],
+        )?;
+        let db_pool_max_connections = IntGauge::new(
+            "db_pool_max_connections",
+            "Maximum number of database pool connections",
+        )?;
+        let db_size = Gauge::new("db_size_mb", "Database size in megabytes")?;
+
+        registry.register(Box::new(tasks.clone()))?;
+        registry.register(Box::new(tasks_handed_out.clone()))?;
+        registry.register(Box::new(tasks_submitted.clone()))?;
+        registry.register(Box::new(tasks_reverted.clone()))?;
+        registry.register(Box::new(submission_bytes.clone()))?;
+        registry.register(Box::new(submission_papers.clone()))?;
+        registry.register(Box::new(ingest_duration.clone()))?;
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(db_pool_connections.clone()))?;
+        registry.register(Box::new(db_pool_max_connections.clone()))?;
+        registry.register(Box::new(db_size.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            tasks,
+            tasks_handed_out,
+            tasks_submitted,
+            tasks_reverted,
+            submission_bytes,
+            submission_papers,
+            ingest_duration,
+            http_requests,
+            db_pool_connections,
+            db_pool_max_connections,
+            db_size,
+        })
+    }
+
+    /// Render all metrics in Prometheus text format.
+    pub fn encode(&self) -> prometheus::Result<String> {
+        TextEncoder::new().encode_to_string(&self.registry.gather())
+    }
+
+    pub fn content_type(&self) -> String {
+        TextEncoder::new().format_type().to_string()
+    }
+}
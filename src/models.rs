@@ -8,41 +8,122 @@ pub struct Paper {
     pub description: String,
     pub submission_date: chrono::NaiveDate,
     pub body: String,
+    pub version: i32,
 }
 
-#[derive(PartialEq, Clone, Debug)]
+/// Paper with its authors and subjects.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct PaperFull {
+    #[serde(flatten)]
+    pub paper: Paper,
+    pub authors: Vec<Author>,
+    pub subjects: Vec<Subject>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct PaperVersionInfo {
+    pub version: i32,
+    pub title: String,
+    /// Missing for the current version
+    pub superseded_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct PaperVersion {
+    pub arxiv_id: String,
+    pub version: i32,
+    pub title: String,
+    pub description: String,
+    pub body: String,
+    /// Missing for the current version
+    pub superseded_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(PartialEq, Clone, Debug, serde::Serialize, utoipa::ToSchema)]
 pub struct Author {
     pub id: Id,
     pub name: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
 pub struct Subject {
     pub id: Id,
     pub name: String,
 }
 
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct AuthorStats {
+    pub id: Id,
+    pub name: String,
+    pub paper_count: i64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct SubjectStats {
+    pub id: Id,
+    pub name: String,
+    pub paper_count: i64,
+}
+
+/// Paper without its body, used in listings.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct PaperSummary {
+    pub id: Id,
+    pub arxiv_id: String,
+    pub title: String,
+    pub description: String,
+    pub submission_date: chrono::NaiveDate,
+    pub version: i32,
+}
+
+/// Paper with only requested fields present.
+#[derive(Clone, Debug, Default, serde::Serialize, utoipa::ToSchema)]
+pub struct PaperProjection {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Id>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arxiv_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submission_date: Option<chrono::NaiveDate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i32>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct PapersCursorPage {
+    pub papers: Vec<PaperProjection>,
+    /// Pass as `cursor` to get the next page, missing on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct PapersPage {
+    pub total: i64,
+    pub offset: i64,
+    pub limit: i64,
+    pub papers: Vec<PaperSummary>,
+}
+
+#[allow(unused)]
 #[derive(Clone, Debug)]
 pub struct PaperAuthor {
     pub paper_id: Id,
     pub author_id: Id,
 }
 
+#[allow(unused)]
 #[derive(Clone, Debug)]
 pub struct PaperSubject {
     pub paper_id: Id,
     pub subject_id: Id,
 }
 
-#[derive(Clone, Debug)]
-pub struct NewPaper {
-    pub arxiv_id: String,
-    pub title: String,
-    pub description: String,
-    pub submission_date: chrono::NaiveDate,
-    pub body: String,
-}
-
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct NewPaperFull {
     pub arxiv_id: String,
@@ -50,10 +131,17 @@ pub struct NewPaperFull {
     pub description: String,
     pub submission_date: chrono::NaiveDate,
     pub body: String,
+    /// arXiv version of the paper, 1 if not specified
+    #[serde(default = "first_version")]
+    pub version: i32,
     pub authors: Vec<NewAuthor>,
     pub subjects: Vec<NewSubject>,
 }
 
+fn first_version() -> i32 {
+    1
+}
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize, utoipa::ToSchema)]
 pub struct NewAuthor {
     pub name: String,
@@ -64,18 +152,110 @@ pub struct NewSubject {
     pub name: String,
 }
 
+#[derive(Clone, Debug, serde::Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct PaperSearchHit {
+    pub id: Id,
+    pub arxiv_id: String,
+    pub title: String,
+    pub submission_date: chrono::NaiveDate,
+    pub rank: f32,
+    /// Fragment of paper description with matched words wrapped in `<b></b>`
+    pub snippet: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct SearchResults {
+    pub total: i64,
+    pub offset: i64,
+    pub limit: i64,
+    pub papers: Vec<PaperSearchHit>,
+}
+
+/// What happened to papers of a submission.
+#[derive(Clone, Debug, Default, serde::Serialize, utoipa::ToSchema)]
+pub struct IngestionReport {
+    pub inserted: usize,
+    /// Papers which got a new revision
+    pub updated: usize,
+    /// Papers which are already archived with the same content or a newer version
+    pub skipped: Vec<String>,
+    /// Papers which are already archived with the same version but different
+    /// content, they are left unchanged. A new version is needed to update them
+    pub unchanged_version_conflicts: Vec<String>,
+    pub rejected: Vec<RejectedPaper>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct RejectedPaper {
+    /// Missing if the paper could not be parsed
+    pub arxiv_id: Option<String>,
+    pub reason: String,
+}
+
+/// Paper of a submission which did not pass validation.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct InvalidPaper {
+    /// Line of the submitted file
+    pub line: usize,
+    /// Missing if the paper could not be parsed
+    pub arxiv_id: Option<String>,
+    pub errors: Vec<String>,
+    #[serde(skip)]
+    pub paper: Option<NewPaperFull>,
+}
+
+/// Submission is rejected as a whole if any of its papers is invalid.
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct SubmissionErrors {
+    /// Number of invalid papers, only the first ones are listed
+    pub total: usize,
+    pub papers: Vec<InvalidPaper>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct QuarantinedPaper {
+    pub id: i32,
+    pub submission_date: chrono::NaiveDate,
+    pub category: Option<String>,
+    pub worker: Option<String>,
+    pub line: i32,
+    pub arxiv_id: Option<String>,
+    /// Submitted paper, missing if it could not be parsed
+    #[schema(value_type = Option<Object>)]
+    pub paper: Option<serde_json::Value>,
+    pub errors: Vec<String>,
+    pub created_at: chrono::NaiveDateTime,
+}
+
 #[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
 pub struct ArchiveStats {
     pub paper_count: i64,
     pub db_size_mb: f64,
 }
 
-#[derive(Clone, Debug, serde::Serialize, sqlx::Type)]
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    serde::Serialize,
+    serde::Deserialize,
+    sqlx::Type,
+    utoipa::ToSchema,
+)]
 #[sqlx(type_name = "status", rename_all = "lowercase")]
 pub enum Status {
+    #[serde(alias = "idle")]
     Idle,
+    #[serde(alias = "processing")]
     Processing,
+    #[serde(alias = "done")]
     Done,
+    #[serde(alias = "failed")]
+    Failed,
+    #[serde(alias = "cancelled")]
+    Cancelled,
 }
 
 impl sqlx::postgres::PgHasArrayType for Status {
@@ -84,17 +264,92 @@ impl sqlx::postgres::PgHasArrayType for Status {
     }
 }
 
+/// Access level of API key. Every scope includes the ones before it.
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    serde::Serialize,
+    sqlx::Type,
+    clap::ValueEnum,
+)]
+#[sqlx(type_name = "scope", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Archive queries and stats
+    Read,
+    /// Taking and submitting tasks
+    Scraper,
+    /// Creating tasks and importing papers
+    Admin,
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    pub scope: Scope,
+    pub created_at: chrono::NaiveDateTime,
+}
+
 #[derive(Clone, Debug, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
 pub struct NewTask {
     pub submission_date: chrono::NaiveDate,
+    /// arXiv category like `cs.LG`, the whole day if missing
+    pub category: Option<String>,
 }
 
 #[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
 pub struct Task {
     pub submission_date: chrono::NaiveDate,
+    /// arXiv category, the whole day if missing
+    pub category: Option<String>,
     pub status: Status,
     pub processing_start: Option<chrono::NaiveDateTime>,
     pub processing_end: Option<chrono::NaiveDateTime>,
+    /// Lease of the scraper holding the task, or of the accepted submission
+    /// once the task is done
+    pub lease_id: Option<uuid::Uuid>,
+    pub lease_expires_at: Option<chrono::NaiveDateTime>,
+    /// Worker which took the task last
+    pub worker: Option<String>,
+    /// Worker which submitted the task
+    pub submitted_by: Option<String>,
+    /// How many times the task was given to scrapers
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    /// Task is not given to scrapers until this moment
+    pub retry_after: Option<chrono::NaiveDateTime>,
+    /// Tasks with higher priority are given to scrapers first
+    pub priority: i32,
+    /// Idempotency key or content hash of the accepted submission
+    pub submission_key: Option<String>,
+}
+
+/// Task given to a scraper. Lease must be extended with heartbeats until the
+/// task is submitted, otherwise the task is given to somebody else.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct TaskLease {
+    pub submission_date: chrono::NaiveDate,
+    /// Only papers of this category are to be scraped, the whole day if missing
+    pub category: Option<String>,
+    pub lease_id: uuid::Uuid,
+    pub lease_expires_at: chrono::NaiveDateTime,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct TaskHeartbeat {
+    pub lease_id: uuid::Uuid,
+}
+
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
+pub struct TaskFailure {
+    pub lease_id: uuid::Uuid,
+    pub error: String,
 }
 
 #[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
@@ -102,10 +357,69 @@ pub struct TasksStats {
     pub idle: i64,
     pub processing: i64,
     pub done: i64,
+    pub failed: i64,
+    pub cancelled: i64,
 }
 
-#[derive(Clone, Debug, serde::Deserialize, serde::Serialize, utoipa::ToSchema)]
-pub struct TaskSubmission {
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct TasksPage {
+    /// Number of tasks matching the filter
+    pub total: i64,
+    pub tasks: Vec<Task>,
+}
+
+/// Result of an operation on a range of tasks.
+#[derive(Clone, Debug, Default, serde::Serialize, utoipa::ToSchema)]
+pub struct TaskRangeReport {
+    pub changed: Vec<TaskKey>,
+    /// Tasks left as they are because of their status
+    pub skipped: Vec<SkippedTask>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct SkippedTask {
+    pub submission_date: chrono::NaiveDate,
+    pub category: Option<String>,
+    pub status: Status,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct TaskKey {
+    pub submission_date: chrono::NaiveDate,
+    pub category: Option<String>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct DayCoverage {
     pub submission_date: chrono::NaiveDate,
-    pub papers: Vec<NewPaperFull>,
+    /// Status of the whole day task, or of the least advanced category task
+    /// if the day is split by categories. Missing if there are no tasks
+    pub status: Option<Status>,
+    /// Day is scraped by single categories only, so it is never put to the
+    /// queue as a whole
+    pub split: bool,
+    pub paper_count: i64,
+    /// Usual number of papers on this weekday, missing if there is no data
+    pub expected_count: Option<i64>,
+    /// Day has no papers at all
+    pub missing: bool,
+    /// Day has far less papers than usual for its weekday
+    pub low: bool,
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, utoipa::ToSchema)]
+pub struct CoverageReport {
+    pub days: Vec<DayCoverage>,
+    /// Days which were put to the queue to be scraped
+    pub enqueued: Vec<chrono::NaiveDate>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct Worker {
+    pub name: String,
+    pub last_seen: chrono::NaiveDateTime,
+    pub tasks_in_flight: i64,
+    pub completed: i32,
+    /// Number of tasks which were taken by worker but never submitted
+    pub failed: i32,
 }
@@ -1,48 +1,154 @@
-use crate::handlers::{archive, tasks};
-use crate::models;
+use crate::handlers::{archive, metrics, tasks, workers};
+use crate::{auth::API_KEY_HEADER, models};
 use actix_web::web;
-use utoipa::OpenApi;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
 
 #[derive(OpenApi)]
 #[openapi(
     paths(
         archive::get_stats,
         archive::get_papers_from_day,
+        archive::search_papers,
+        archive::list_papers,
+        archive::export_papers,
+        archive::import_papers,
+        archive::get_paper,
+        archive::get_paper_by_arxiv_id,
+        archive::get_paper_versions,
+        archive::get_paper_version,
+        archive::get_authors,
+        archive::get_author_papers,
+        archive::get_subjects,
+        archive::get_subject_papers,
         tasks::get_task,
+        tasks::list_tasks,
+        tasks::heartbeat,
+        tasks::fail_task,
         tasks::get_stats,
+        tasks::get_coverage,
+        tasks::enqueue_coverage_gaps,
+        tasks::get_task_info,
+        tasks::reset_task,
+        tasks::get_quarantine,
+        tasks::cancel_tasks,
+        tasks::delete_tasks,
+        tasks::delete_task,
         tasks::post_day_as_task,
         tasks::post_month_as_task,
         tasks::post_year_as_task,
         tasks::submit_task,
+        workers::get_workers,
+        metrics::get_metrics,
     ),
     components(schemas(
         models::NewPaperFull,
+        models::IngestionReport,
+        models::RejectedPaper,
+        models::InvalidPaper,
+        models::SubmissionErrors,
+        models::QuarantinedPaper,
         models::Paper,
+        models::PaperFull,
+        models::Author,
+        models::Subject,
+        models::AuthorStats,
+        models::SubjectStats,
+        models::PaperSummary,
+        models::PapersPage,
+        models::PaperProjection,
+        models::PapersCursorPage,
+        models::PaperVersionInfo,
+        models::PaperVersion,
+        models::PaperSearchHit,
+        models::SearchResults,
         models::NewAuthor,
         models::NewSubject,
         models::ArchiveStats,
         models::NewTask,
+        models::TaskLease,
+        models::TaskHeartbeat,
+        models::TaskFailure,
         models::TasksStats,
-        models::TaskSubmission,
+        models::Task,
+        models::Status,
+        models::TasksPage,
+        models::TaskRangeReport,
+        models::SkippedTask,
+        models::TaskKey,
+        models::DayCoverage,
+        models::CoverageReport,
+        models::Worker,
     )),
     tags(
         (name = "tasks", description = "Tasks management api."),
         (name = "archive", description = "Archive management api."),
-    )
+        (name = "workers", description = "Scrapers activity api."),
+        (name = "metrics", description = "Prometheus metrics."),
+    ),
+    modifiers(&SecurityAddon),
+    security(("bearer" = []), ("api_key" = [])),
 )]
 struct ApiDoc;
 
+/// API key can be passed either as bearer token or in `X-Api-Key` header.
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+    }
+}
+
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg.service(
         utoipa_rapidoc::RapiDoc::with_openapi("/api-docs/openapi.json", ApiDoc::openapi())
             .path("/docs"),
     )
     .service(archive::get_stats)
+    .service(archive::search_papers)
+    .service(archive::get_paper_versions)
+    .service(archive::get_paper_version)
+    .service(archive::list_papers)
+    .service(archive::export_papers)
+    .service(archive::import_papers)
+    .service(archive::get_paper)
+    // NOTE: goes after versions, otherwise it would take "{arxiv_id}/versions" as an id
+    .service(archive::get_paper_by_arxiv_id)
+    .service(archive::get_authors)
+    .service(archive::get_author_papers)
+    .service(archive::get_subjects)
+    .service(archive::get_subject_papers)
+    // NOTE: matches any three segments after /archive, so it goes last
     .service(archive::get_papers_from_day)
     .service(tasks::get_task)
+    .service(tasks::heartbeat)
+    .service(tasks::fail_task)
+    .service(tasks::reset_task)
+    .service(tasks::get_quarantine)
+    .service(tasks::cancel_tasks)
     .service(tasks::get_stats)
+    .service(tasks::get_coverage)
+    .service(tasks::enqueue_coverage_gaps)
+    .service(tasks::list_tasks)
+    // NOTE: goes after other paths under /tasks, otherwise it would take them as a date
+    .service(tasks::get_task_info)
+    .service(tasks::delete_tasks)
+    .service(tasks::delete_task)
     .service(tasks::post_day_as_task)
     .service(tasks::post_month_as_task)
     .service(tasks::post_year_as_task)
-    .service(tasks::submit_task);
+    .service(tasks::submit_task)
+    .service(workers::get_workers)
+    .service(metrics::get_metrics);
 }
@@ -0,0 +1,245 @@
+use crate::{
+    file_upload::Record,
+    models::{InvalidPaper, NewPaperFull},
+};
+
+const MAX_TITLE_LEN: usize = 1000;
+const MAX_DESCRIPTION_LEN: usize = 10_000;
+const MAX_BODY_LEN: usize = 64 * 1024 * 1024;
+const MAX_NAME_LEN: usize = 1000;
+const MAX_AUTHORS: usize = 10_000;
+const MAX_SUBJECTS: usize = 100;
+
+/// New style arXiv id like `2403.01234` (four digit number before 2015), or
+/// old style one like `hep-th/9901001` or `math.GT/0309136`.
+pub fn is_valid_arxiv_id(arxiv_id: &str) -> bool {
+    let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+
+    if let Some((archive, number)) = arxiv_id.split_once('/') {
+        let (archive, class) = archive.split_once('.').unwrap_or((archive, "AA"));
+        return !archive.is_empty()
+            && archive.bytes().all(|b| b.is_ascii_lowercase() || b == b'-')
+            && class.len() == 2
+            && class.bytes().all(|b| b.is_ascii_uppercase())
+            && number.len() == 7
+            && all_digits(number);
+    }
+
+    match arxiv_id.split_once('.') {
+        Some((month, number)) => {
+            let digits = if month < "1501" { 4 } else { 5 };
+            month.len() == 4 && all_digits(month) && number.len() == digits && all_digits(number)
+        }
+        None => false,
+    }
+}
+
+/// Everything wrong with a paper, which has to be submitted on the given day
+/// if there is one.
+pub fn check_paper(
+    paper: &NewPaperFull,
+    submission_date: Option<chrono::NaiveDate>,
+) -> Vec<String> {
+    let mut errors = Vec::new();
+
+    if !is_valid_arxiv_id(&paper.arxiv_id) {
+        errors.push(format!("Invalid arXiv id {:?}", paper.arxiv_id));
+    }
+    if let Some(submission_date) = submission_date {
+        if paper.submission_date != submission_date {
+            errors.push(format!(
+                "Submission date {} does not match task date {submission_date}",
+                paper.submission_date
+            ));
+        }
+    }
+    if paper.version < 1 {
+        errors.push(format!("Invalid version {}", paper.version));
+    }
+
+    if paper.title.trim().is_empty() {
+        errors.push("Empty title".to_string());
+    } else if paper.title.len() > MAX_TITLE_LEN {
+        errors.push(format!("Title is longer than {MAX_TITLE_LEN} bytes"));
+    }
+    if paper.description.trim().is_empty() {
+        errors.push("Empty description".to_string());
+    } else if paper.description.len() > MAX_DESCRIPTION_LEN {
+        errors.push(format!(
+            "Description is longer than {MAX_DESCRIPTION_LEN} bytes"
+        ));
+    }
+    if paper.body.trim().is_empty() {
+        errors.push("Empty body".to_string());
+    } else if paper.body.len() > MAX_BODY_LEN {
+        errors.push(format!("Body is longer than {MAX_BODY_LEN} bytes"));
+    }
+
+    if paper.authors.is_empty() {
+        errors.push("No authors".to_string());
+    } else if paper.authors.len() > MAX_AUTHORS {
+        errors.push(format!("More than {MAX_AUTHORS} authors"));
+    }
+    if paper
+        .authors
+        .iter()
+        .any(|author| !is_valid_name(&author.name))
+    {
+        errors.push("Empty or too long author name".to_string());
+    }
+
+    if paper.subjects.is_empty() {
+        errors.push("No subjects".to_string());
+    } else if paper.subjects.len() > MAX_SUBJECTS {
+        errors.push(format!("More than {MAX_SUBJECTS} subjects"));
+    }
+    if paper
+        .subjects
+        .iter()
+        .any(|subject| !is_valid_name(&subject.name))
+    {
+        errors.push("Empty or too long subject name".to_string());
+    }
+
+    errors
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.trim().is_empty() && name.len() <= MAX_NAME_LEN
+}
+
+/// Split records of a submission into valid papers and invalid ones, the
+/// latter are added to `invalid`. Invalid papers themselves are kept only if
+/// `keep_papers` is set, e.g. to quarantine them.
+pub fn check_records(
+    records: Vec<Record<NewPaperFull>>,
+    submission_date: chrono::NaiveDate,
+    invalid: &mut Vec<InvalidPaper>,
+    keep_papers: bool,
+) -> Vec<NewPaperFull> {
+    let mut papers = Vec::with_capacity(records.len());
+    for Record { line, value } in records {
+        let paper = match value {
+            Ok(paper) => paper,
+            Err(e) => {
+                invalid.push(InvalidPaper {
+                    line,
+                    arxiv_id: None,
+                    errors: vec![e.to_string()],
+                    paper: None,
+                });
+                continue;
+            }
+        };
+
+        let errors = check_paper(&paper, Some(submission_date));
+        if errors.is_empty() {
+            papers.push(paper);
+        } else {
+            invalid.push(InvalidPaper {
+                line,
+                arxiv_id: Some(paper.arxiv_id.clone()),
+                errors,
+                paper: keep_papers.then_some(paper),
+            });
+        }
+    }
+    papers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{NewAuthor, NewSubject};
+
+    fn date(day: u32) -> chrono::NaiveDate {
+        chrono::NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+    }
+
+    fn paper() -> NewPaperFull {
+        NewPaperFull {
+            arxiv_id: "2403.01234".to_string(),
+            title: "Title".to_string(),
+            description: "Description".to_string(),
+            submission_date: date(4),
+            body: "Body".to_string(),
+            version: 1,
+            authors: vec![NewAuthor {
+                name: "Author".to_string(),
+            }],
+            subjects: vec![NewSubject {
+                name: "cs.LG".to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn old_style_ids() {
+        assert!(is_valid_arxiv_id("hep-th/9901001"));
+        assert!(is_valid_arxiv_id("math.GT/0309136"));
+        assert!(!is_valid_arxiv_id("hep-th/990100"));
+        assert!(!is_valid_arxiv_id("HEP-TH/9901001"));
+        assert!(!is_valid_arxiv_id("math.gt/0309136"));
+        assert!(!is_valid_arxiv_id("/9901001"));
+    }
+
+    #[test]
+    fn new_style_ids() {
+        assert!(is_valid_arxiv_id("0704.0001"));
+        assert!(is_valid_arxiv_id("1412.9999"));
+        assert!(is_valid_arxiv_id("1501.00001"));
+        assert!(is_valid_arxiv_id("2403.01234"));
+        assert!(!is_valid_arxiv_id("1412.00001"));
+        assert!(!is_valid_arxiv_id("2403.0123"));
+        assert!(!is_valid_arxiv_id("2403.012345"));
+        assert!(!is_valid_arxiv_id("24a3.01234"));
+        assert!(!is_valid_arxiv_id("2403"));
+    }
+
+    #[test]
+    fn valid_paper() {
+        assert!(check_paper(&paper(), Some(date(4))).is_empty());
+    }
+
+    #[test]
+    fn empty_title_and_body() {
+        let paper = NewPaperFull {
+            title: " ".to_string(),
+            body: String::new(),
+            ..paper()
+        };
+        assert_eq!(
+            check_paper(&paper, Some(date(4))),
+            ["Empty title", "Empty body"]
+        );
+    }
+
+    #[test]
+    fn date_outside_of_task_day() {
+        let errors = check_paper(&paper(), Some(date(5)));
+        assert_eq!(
+            errors,
+            ["Submission date 2024-03-04 does not match task date 2024-03-05"]
+        );
+        assert!(check_paper(&paper(), None).is_empty());
+    }
+
+    #[test]
+    fn invalid_papers_are_kept_on_demand() {
+        let record = || Record {
+            line: 1,
+            value: Ok(NewPaperFull {
+                title: String::new(),
+                ..paper()
+            }),
+        };
+
+        for keep_papers in [false, true] {
+            let mut invalid = Vec::new();
+            let papers = check_records(vec![record()], date(4), &mut invalid, keep_papers);
+            assert!(papers.is_empty());
+            assert_eq!(invalid.len(), 1);
+            assert_eq!(invalid[0].paper.is_some(), keep_papers);
+        }
+    }
+}